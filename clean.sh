@@ -2,6 +2,6 @@
 
 set -x
 
-rm -f a.out build/*
+rm -rf a.out build/*
 touch build/.gitkeep
 rm -rf target/
@@ -0,0 +1,38 @@
+// Copyright (C) 2025 Filip Chovanec
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// host for modules built by the wasm backend, provides the std/io link-ins
+// usage: node native/wasm/run.mjs module.wasm
+
+import { readFileSync } from "node:fs";
+
+let exports;
+
+// strings are zero terminated, like in native/stdc
+function readString(address) {
+	const memory = new Uint8Array(exports.memory.buffer);
+	let end = address;
+	while (memory[end] !== 0) end++;
+	return new TextDecoder().decode(memory.subarray(address, end));
+}
+
+const env = {
+	_x_print_string: () => process.stdout.write(readString(Number(exports.dryft_pop()))),
+	_x_print_int: () => process.stdout.write(exports.dryft_pop().toString()),
+};
+
+const module = new WebAssembly.Module(readFileSync(process.argv[2]));
+exports = new WebAssembly.Instance(module, { env }).exports;
+exports.main();
@@ -0,0 +1,98 @@
+/*
+* Copyright (C) 2025 Filip Chovanec
+*
+* This program is free software: you can redistribute it and/or modify
+* it under the terms of the GNU General Public License as published by
+* the Free Software Foundation, either version 3 of the License, or
+* (at your option) any later version.
+*
+* This program is distributed in the hope that it will be useful,
+* but WITHOUT ANY WARRANTY; without even the implied warranty of
+* MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+* GNU General Public License for more details.
+*
+* You should have received a copy of the GNU General Public License
+* along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+// parse tree shared by codegen and any tooling that needs to understand dryft code
+// every node keeps the token it was read from, so later passes can point at the source
+
+use crate::lexer::Token;
+use crate::state::{MethodClass, ValueTypes};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Program {
+    pub definitions: Vec<Definition>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Definition {
+    Fun(MethodDef),
+    Act(MethodDef),
+    Linkin(Linkin),
+    Module(Vec<Definition>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MethodDef {
+    pub name: String,
+    pub token: Token, // the name
+    pub signature: Option<Signature>,
+    pub body: Body,
+}
+
+// stack effect declared by an annotation like (Int Int -> Bool), both sides are listed bottom to top
+#[derive(Debug, Clone, PartialEq)]
+pub struct Signature {
+    pub inputs: Vec<ValueTypes>,
+    pub outputs: Vec<ValueTypes>,
+    pub token: Token,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Linkin {
+    pub class: MethodClass,
+    pub name: String,
+    pub token: Token,
+    pub signature: Option<Signature>, // written after the name, we can not infer it from foreign code
+}
+
+// code between a block opener and its terminator
+#[derive(Debug, Clone, PartialEq)]
+pub struct Body {
+    pub nodes: Vec<Node>,
+    pub end: Token, // the terminator
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Block {
+    Then(Body),
+    When(Body),
+    Cycle(Body),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Literal {
+    Integer(String),
+    Text(String),
+    Binary(bool),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum NodeKind {
+    Word(String), // builtin or user method
+    Literal(Literal),
+    VarDef(String),
+    VarRead(String),
+    VarWrite(String),
+    Block(Block),
+    Break,
+    Return,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Node {
+    pub kind: NodeKind,
+    pub token: Token,
+}
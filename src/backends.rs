@@ -16,8 +16,13 @@
 */
 
 pub mod c99;
+pub mod llvm;
+pub mod vm;
+pub mod wasm;
 pub mod x86;
 
+use std::cell::Cell;
+
 pub trait Backend {
     // insert the locally compiled snipet into the full holistic-program (with all its dependencies)
     fn complete(&self, compiled: &str) -> String;
@@ -68,6 +73,17 @@ pub trait Backend {
     fn read_variable(&self, name: &str) -> String;
     fn write_variable(&self, name: &str) -> String;
 
+    // variables of the top level scope, only code typed into the REPL has those
+    fn create_global(&self, name: &str) -> String {
+        self.create_variable(name)
+    }
+    fn read_global(&self, name: &str) -> String {
+        self.read_variable(name)
+    }
+    fn write_global(&self, name: &str) -> String {
+        self.write_variable(name)
+    }
+
     fn create_conditional_statement(&self, _body: String, _inelect: bool) -> String {
         todo!()
     }
@@ -87,10 +103,98 @@ pub trait Backend {
     }
 }
 
+// dryft strings use C escapes, backends that store them as plain bytes use this
+pub fn string_bytes(s: &str) -> Vec<u8> {
+    let mut bytes = vec![];
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        let c = match c {
+            '\\' => match chars.next() {
+                Some('n') => '\n',
+                Some('t') => '\t',
+                Some('r') => '\r',
+                Some('0') => '\0',
+                Some(other) => other,
+                None => '\\',
+            },
+            c => c,
+        };
+        let mut buf = [0; 4];
+        bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+    }
+    bytes.push(0);
+    bytes
+}
+
+// jumps whose target is only known once the enclosing block is created
+pub const LOOP_END: &str = "{loop end}";
+pub const WHEN_END: &str = "{when end}";
+pub const METHOD_END: &str = "{method end}";
+
+// labels and names a backend generates have to be unique in the whole output
+#[derive(Default)]
+pub struct Labels(Cell<usize>);
+
+impl Labels {
+    pub fn next(&self) -> usize {
+        let n = self.0.get();
+        self.0.set(n + 1);
+        n
+    }
+}
+
+// everything but letters and digits is escaped as hex, so user text never ends up as code
+pub fn hex_string(bytes: &[u8], upper: bool) -> String {
+    bytes
+        .iter()
+        .map(|b| match (b.is_ascii_alphanumeric(), upper) {
+            (true, _) => (*b as char).to_string(),
+            (false, true) => format!("\\{b:02X}"),
+            (false, false) => format!("\\{b:02x}"),
+        })
+        .collect()
+}
+
+// a function body taken apart by create_function()
+pub struct FunctionBody<'a> {
+    pub hoisted: String,    // lines that go before the function
+    pub vars: Vec<&'a str>, // every variable once, in order of creation
+    pub code: String,       // what is left
+}
+
+// lines starting with `hoist` are moved out of the body, lines starting with `var` name a variable
+pub fn split_body<'a>(body: &'a str, hoist: Option<&str>, var: &str) -> FunctionBody<'a> {
+    let mut out = FunctionBody {
+        hoisted: String::new(),
+        vars: vec![],
+        code: String::new(),
+    };
+    for line in body.lines() {
+        if let Some(hoisted) = hoist.and_then(|h| line.strip_prefix(h)) {
+            out.hoisted.push_str(hoisted);
+            out.hoisted.push('\n');
+        } else if let Some(name) = line.strip_prefix(var) {
+            if !out.vars.contains(&name) {
+                out.vars.push(name);
+            }
+        } else {
+            out.code.push_str(line);
+            out.code.push('\n');
+        }
+    }
+    out
+}
+
+// every name select() knows, target descriptors are checked against it
+pub const NAMES: &[&str] = &["C99", "x86", "LLVM", "wasm", "vm"];
+
 pub fn select(name: &str) -> Box<dyn Backend> {
     match name {
         "C99" => Box::new(c99::C99Backend {}),
-        "x86" => Box::new(x86::Nasm64Backend {}),
+        "x86" => Box::new(x86::Nasm64Backend::default()),
+        "LLVM" => Box::new(llvm::LlvmBackend::default()),
+        "wasm" => Box::new(wasm::WatBackend::default()),
+        "vm" => Box::new(vm::VmBackend::default()),
         other => panic!("Invalid backend {other}"),
     }
 }
@@ -0,0 +1,191 @@
+; Copyright (C) 2025 Filip Chovanec
+;
+; This program is free software: you can redistribute it and/or modify
+; it under the terms of the GNU General Public License as published by
+; the Free Software Foundation, either version 3 of the License, or
+; (at your option) any later version.
+;
+; This program is distributed in the hope that it will be useful,
+; but WITHOUT ANY WARRANTY; without even the implied warranty of
+; MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+; GNU General Public License for more details.
+;
+; You should have received a copy of the GNU General Public License
+; along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+; the data stack, sptr is the index of the first free slot
+
+@stack = global [1000 x i64] zeroinitializer
+@sptr = global i64 0
+
+define void @dryft_push(i64 %x) {
+  %i = load i64, i64* @sptr
+  %p = getelementptr [1000 x i64], [1000 x i64]* @stack, i64 0, i64 %i
+  store i64 %x, i64* %p
+  %n = add i64 %i, 1
+  store i64 %n, i64* @sptr
+  ret void
+}
+
+define i64 @dryft_pop() {
+  %i = load i64, i64* @sptr
+  %n = sub i64 %i, 1
+  store i64 %n, i64* @sptr
+  %p = getelementptr [1000 x i64], [1000 x i64]* @stack, i64 0, i64 %n
+  %x = load i64, i64* %p
+  ret i64 %x
+}
+
+define internal void @builtin_copy() {
+  %a = call i64 @dryft_pop()
+  call void @dryft_push(i64 %a)
+  call void @dryft_push(i64 %a)
+  ret void
+}
+
+define internal void @builtin_swap() {
+  %b = call i64 @dryft_pop()
+  %a = call i64 @dryft_pop()
+  call void @dryft_push(i64 %b)
+  call void @dryft_push(i64 %a)
+  ret void
+}
+
+define internal void @builtin_add() {
+  %b = call i64 @dryft_pop()
+  %a = call i64 @dryft_pop()
+  %r = add i64 %a, %b
+  call void @dryft_push(i64 %r)
+  ret void
+}
+
+define internal void @builtin_sub() {
+  %b = call i64 @dryft_pop()
+  %a = call i64 @dryft_pop()
+  %r = sub i64 %a, %b
+  call void @dryft_push(i64 %r)
+  ret void
+}
+
+define internal void @builtin_mul() {
+  %b = call i64 @dryft_pop()
+  %a = call i64 @dryft_pop()
+  %r = mul i64 %a, %b
+  call void @dryft_push(i64 %r)
+  ret void
+}
+
+define internal void @builtin_div() {
+  %b = call i64 @dryft_pop()
+  %a = call i64 @dryft_pop()
+  %r = sdiv i64 %a, %b
+  call void @dryft_push(i64 %r)
+  ret void
+}
+
+define internal void @builtin_mod() {
+  %b = call i64 @dryft_pop()
+  %a = call i64 @dryft_pop()
+  %r = srem i64 %a, %b
+  call void @dryft_push(i64 %r)
+  ret void
+}
+
+define internal void @builtin_xor() {
+  %b = call i64 @dryft_pop()
+  %a = call i64 @dryft_pop()
+  %r = xor i64 %a, %b
+  call void @dryft_push(i64 %r)
+  ret void
+}
+
+define internal void @builtin_simple_equality() {
+  %b = call i64 @dryft_pop()
+  %a = call i64 @dryft_pop()
+  %c = icmp eq i64 %a, %b
+  %r = zext i1 %c to i64
+  call void @dryft_push(i64 %r)
+  ret void
+}
+
+define internal void @builtin_simple_non_equality() {
+  %b = call i64 @dryft_pop()
+  %a = call i64 @dryft_pop()
+  %c = icmp ne i64 %a, %b
+  %r = zext i1 %c to i64
+  call void @dryft_push(i64 %r)
+  ret void
+}
+
+define internal void @builtin_num_greater() {
+  %b = call i64 @dryft_pop()
+  %a = call i64 @dryft_pop()
+  %c = icmp sgt i64 %a, %b
+  %r = zext i1 %c to i64
+  call void @dryft_push(i64 %r)
+  ret void
+}
+
+define internal void @builtin_num_greater_or_equal() {
+  %b = call i64 @dryft_pop()
+  %a = call i64 @dryft_pop()
+  %c = icmp sge i64 %a, %b
+  %r = zext i1 %c to i64
+  call void @dryft_push(i64 %r)
+  ret void
+}
+
+define internal void @builtin_num_less_than() {
+  %b = call i64 @dryft_pop()
+  %a = call i64 @dryft_pop()
+  %c = icmp slt i64 %a, %b
+  %r = zext i1 %c to i64
+  call void @dryft_push(i64 %r)
+  ret void
+}
+
+define internal void @builtin_num_less_than_or_equal() {
+  %b = call i64 @dryft_pop()
+  %a = call i64 @dryft_pop()
+  %c = icmp sle i64 %a, %b
+  %r = zext i1 %c to i64
+  call void @dryft_push(i64 %r)
+  ret void
+}
+
+define internal void @builtin_logical_not() {
+  %a = call i64 @dryft_pop()
+  %c = icmp eq i64 %a, 0
+  %r = zext i1 %c to i64
+  call void @dryft_push(i64 %r)
+  ret void
+}
+
+define internal void @builtin_logical_and() {
+  %b = call i64 @dryft_pop()
+  %a = call i64 @dryft_pop()
+  %bc = icmp ne i64 %b, 0
+  %ac = icmp ne i64 %a, 0
+  %c = and i1 %ac, %bc
+  %r = zext i1 %c to i64
+  call void @dryft_push(i64 %r)
+  ret void
+}
+
+define internal void @builtin_logical_or() {
+  %b = call i64 @dryft_pop()
+  %a = call i64 @dryft_pop()
+  %bc = icmp ne i64 %b, 0
+  %ac = icmp ne i64 %a, 0
+  %c = or i1 %ac, %bc
+  %r = zext i1 %c to i64
+  call void @dryft_push(i64 %r)
+  ret void
+}
+
+; main() in native/stdc calls us
+define void @dryft_main() {
+  call void @"fun_main"()
+  ret void
+}
+
@@ -0,0 +1,221 @@
+/*
+* Copyright (C) 2025 Filip Chovanec
+*
+* This program is free software: you can redistribute it and/or modify
+* it under the terms of the GNU General Public License as published by
+* the Free Software Foundation, either version 3 of the License, or
+* (at your option) any later version.
+*
+* This program is distributed in the hope that it will be useful,
+* but WITHOUT ANY WARRANTY; without even the implied warranty of
+* MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+* GNU General Public License for more details.
+*
+* You should have received a copy of the GNU General Public License
+* along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+// textual LLVM IR, the data stack is a global array like in base.c
+// every word becomes a call, so the optimizer gets to inline them
+
+use crate::backends::{
+    hex_string, split_body, string_bytes, Backend, Labels, LOOP_END, METHOD_END, WHEN_END,
+};
+
+// lines that create_function() moves out of the body
+const HOIST: &str = "; hoist ";
+const VAR_MARKER: &str = "; var ";
+
+#[derive(Default)]
+pub struct LlvmBackend {
+    names: Labels,
+}
+
+impl LlvmBackend {
+    // SSA values, labels and globals all have to be unique
+    fn name(&self) -> usize {
+        self.names.next()
+    }
+
+    // a block has to end after a jump, whatever follows it is unreachable
+    fn jump(&self, target: &str) -> String {
+        format!("  br label %{target}\ndead.{}:\n", self.name())
+    }
+}
+
+impl Backend for LlvmBackend {
+    fn fun_exclusive_or(&self) -> &'static str {
+        "  call void @builtin_xor()\n"
+    }
+
+    fn create_elect_block(&self, body: String) -> String {
+        let end = format!("when.end.{}", self.name());
+        format!(
+            "{}  br label %{end}\n{end}:\n",
+            body.replace(WHEN_END, &end)
+        )
+    }
+
+    fn method_return(&self) -> String {
+        self.jump(METHOD_END)
+    }
+
+    fn create_conditional_statement(&self, body: String, inelect: bool) -> String {
+        let n = self.name();
+        let after = if inelect {
+            WHEN_END.to_string()
+        } else {
+            format!("endif.{n}")
+        };
+        format!(
+            "  %c.{n} = call i64 @dryft_pop()\n  %b.{n} = icmp ne i64 %c.{n}, 0\n  br i1 %b.{n}, label %then.{n}, label %endif.{n}\nthen.{n}:\n{body}  br label %{after}\nendif.{n}:\n"
+        )
+    }
+
+    fn fun_num_less_than_or_equal(&self) -> &'static str {
+        "  call void @builtin_num_less_than_or_equal()\n"
+    }
+
+    fn fun_num_less_than(&self) -> &'static str {
+        "  call void @builtin_num_less_than()\n"
+    }
+
+    fn fun_num_greater_or_equal(&self) -> &'static str {
+        "  call void @builtin_num_greater_or_equal()\n"
+    }
+
+    fn write_variable(&self, name: &str) -> String {
+        let n = self.name();
+        format!("  %v.{n} = call i64 @dryft_pop()\n  store i64 %v.{n}, i64* %\"var_{name}\"\n")
+    }
+
+    fn read_variable(&self, name: &str) -> String {
+        let n = self.name();
+        format!("  %v.{n} = load i64, i64* %\"var_{name}\"\n  call void @dryft_push(i64 %v.{n})\n")
+    }
+
+    fn create_variable(&self, name: &str) -> String {
+        format!("{VAR_MARKER}{name}\n{}", self.write_variable(name))
+    }
+
+    fn loop_break(&self) -> String {
+        self.jump(LOOP_END)
+    }
+
+    fn fun_num_greater(&self) -> &'static str {
+        "  call void @builtin_num_greater()\n"
+    }
+
+    fn fun_logical_not(&self) -> &'static str {
+        "  call void @builtin_logical_not()\n"
+    }
+
+    fn fun_logical_and(&self) -> &'static str {
+        "  call void @builtin_logical_and()\n"
+    }
+
+    fn fun_logical_or(&self) -> &'static str {
+        "  call void @builtin_logical_or()\n"
+    }
+
+    fn create_loop_block(&self, body: String) -> String {
+        let n = self.name();
+        format!(
+            "  br label %loop.{n}\nloop.{n}:\n{}  br label %loop.{n}\nloop.end.{n}:\n",
+            body.replace(LOOP_END, &format!("loop.end.{n}"))
+        )
+    }
+
+    fn complete(&self, compiled: &str) -> String {
+        let mut base = include_str!("base.ll").to_string();
+        base.push_str(compiled);
+        base
+    }
+
+    fn fun_simple_equality(&self) -> &'static str {
+        "  call void @builtin_simple_equality()\n"
+    }
+
+    fn fun_simple_non_equality(&self) -> &'static str {
+        "  call void @builtin_simple_non_equality()\n"
+    }
+
+    fn fun_swap(&self) -> &'static str {
+        "  call void @builtin_swap()\n"
+    }
+
+    fn linkin_function(&self, name: &str) -> String {
+        format!(
+            "declare void @\"{name}\"()\n\ndefine void @\"fun_{name}\"() {{\n  call void @\"{name}\"()\n  ret void\n}}\n\n"
+        )
+    }
+
+    fn fun_add(&self) -> &'static str {
+        "  call void @builtin_add()\n"
+    }
+
+    fn fun_sub(&self) -> &'static str {
+        "  call void @builtin_sub()\n"
+    }
+
+    fn fun_mul(&self) -> &'static str {
+        "  call void @builtin_mul()\n"
+    }
+
+    fn fun_div(&self) -> &'static str {
+        "  call void @builtin_div()\n"
+    }
+
+    fn fun_mod(&self) -> &'static str {
+        "  call void @builtin_mod()\n"
+    }
+
+    fn fun_copy(&self) -> &'static str {
+        "  call void @builtin_copy()\n"
+    }
+
+    fn fun_drop(&self) -> &'static str {
+        "  call i64 @dryft_pop()\n"
+    }
+
+    // string constants go before the function, variables get an alloca in the entry block
+    fn create_function(&self, fname: &str, body: String) -> String {
+        let body = split_body(&body, Some(HOIST), VAR_MARKER);
+
+        let end = format!("ret.{}", self.name());
+        let mut out = body.hoisted;
+        out.push_str(&format!("define void @\"fun_{fname}\"() {{\nentry:\n"));
+        for v in body.vars.iter() {
+            out.push_str(&format!("  %\"var_{v}\" = alloca i64\n"));
+        }
+        out.push_str(&body.code.replace(METHOD_END, &end));
+        out.push_str(&format!("  br label %{end}\n{end}:\n  ret void\n}}\n\n"));
+        out
+    }
+
+    fn user_function(&self, fname: &str) -> String {
+        format!("  call void @\"fun_{fname}\"()\n")
+    }
+
+    fn push_integer(&self, i: &str) -> String {
+        format!("  call void @dryft_push(i64 {i})\n")
+    }
+
+    fn push_string(&self, s: &str) -> String {
+        let n = self.name();
+        let bytes = string_bytes(s);
+        let len = bytes.len();
+        format!(
+            "{HOIST}@str.{n} = private unnamed_addr constant [{len} x i8] c\"{}\"\n  %s.{n} = ptrtoint [{len} x i8]* @str.{n} to i64\n  call void @dryft_push(i64 %s.{n})\n",
+            hex_string(&bytes, true)
+        )
+    }
+
+    fn push_true(&self) -> &'static str {
+        "  call void @dryft_push(i64 1)\n"
+    }
+
+    fn push_false(&self) -> &'static str {
+        "  call void @dryft_push(i64 0)\n"
+    }
+}
@@ -0,0 +1,193 @@
+/*
+* Copyright (C) 2025 Filip Chovanec
+*
+* This program is free software: you can redistribute it and/or modify
+* it under the terms of the GNU General Public License as published by
+* the Free Software Foundation, either version 3 of the License, or
+* (at your option) any later version.
+*
+* This program is distributed in the hope that it will be useful,
+* but WITHOUT ANY WARRANTY; without even the implied warranty of
+* MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+* GNU General Public License for more details.
+*
+* You should have received a copy of the GNU General Public License
+* along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+// textual bytecode for the interpreter in src/vm.rs, one instruction per line
+// see vm::assemble() for the format
+
+use crate::backends::{string_bytes, Backend, Labels, LOOP_END, WHEN_END};
+
+#[derive(Default)]
+pub struct VmBackend {
+    labels: Labels,
+}
+
+impl Backend for VmBackend {
+    fn fun_exclusive_or(&self) -> &'static str {
+        "xor\n"
+    }
+
+    fn create_elect_block(&self, body: String) -> String {
+        let end = self.labels.next().to_string();
+        format!("{}label {end}\n", body.replace(WHEN_END, &end))
+    }
+
+    fn method_return(&self) -> String {
+        "ret\n".to_string()
+    }
+
+    fn create_conditional_statement(&self, body: String, inelect: bool) -> String {
+        let skip = self.labels.next();
+        let leave = if inelect {
+            format!("jmp {WHEN_END}\n")
+        } else {
+            String::new()
+        };
+        format!("jz {skip}\n{body}{leave}label {skip}\n")
+    }
+
+    fn fun_num_less_than_or_equal(&self) -> &'static str {
+        "le\n"
+    }
+
+    fn fun_num_less_than(&self) -> &'static str {
+        "lt\n"
+    }
+
+    fn fun_num_greater_or_equal(&self) -> &'static str {
+        "ge\n"
+    }
+
+    fn write_variable(&self, name: &str) -> String {
+        format!("store {name}\n")
+    }
+
+    fn read_variable(&self, name: &str) -> String {
+        format!("load {name}\n")
+    }
+
+    fn create_variable(&self, name: &str) -> String {
+        self.write_variable(name)
+    }
+
+    fn create_global(&self, name: &str) -> String {
+        self.write_global(name)
+    }
+
+    fn read_global(&self, name: &str) -> String {
+        format!("gload {name}\n")
+    }
+
+    fn write_global(&self, name: &str) -> String {
+        format!("gstore {name}\n")
+    }
+
+    fn loop_break(&self) -> String {
+        format!("jmp {LOOP_END}\n")
+    }
+
+    fn fun_num_greater(&self) -> &'static str {
+        "gt\n"
+    }
+
+    fn fun_logical_not(&self) -> &'static str {
+        "not\n"
+    }
+
+    fn fun_logical_and(&self) -> &'static str {
+        "and\n"
+    }
+
+    fn fun_logical_or(&self) -> &'static str {
+        "or\n"
+    }
+
+    fn create_loop_block(&self, body: String) -> String {
+        let start = self.labels.next();
+        let end = self.labels.next().to_string();
+        format!(
+            "label {start}\n{}jmp {start}\nlabel {end}\n",
+            body.replace(LOOP_END, &end)
+        )
+    }
+
+    fn complete(&self, compiled: &str) -> String {
+        format!("; dryft vm bytecode\n{compiled}")
+    }
+
+    fn fun_simple_equality(&self) -> &'static str {
+        "eq\n"
+    }
+
+    fn fun_simple_non_equality(&self) -> &'static str {
+        "ne\n"
+    }
+
+    fn fun_swap(&self) -> &'static str {
+        "swap\n"
+    }
+
+    // the vm provides linked in symbols itself, see vm::Native
+    fn linkin_function(&self, name: &str) -> String {
+        format!("fun {name}\nnative {name}\nret\n")
+    }
+
+    fn fun_add(&self) -> &'static str {
+        "add\n"
+    }
+
+    fn fun_sub(&self) -> &'static str {
+        "sub\n"
+    }
+
+    fn fun_mul(&self) -> &'static str {
+        "mul\n"
+    }
+
+    fn fun_div(&self) -> &'static str {
+        "div\n"
+    }
+
+    fn fun_mod(&self) -> &'static str {
+        "mod\n"
+    }
+
+    fn fun_copy(&self) -> &'static str {
+        "copy\n"
+    }
+
+    fn fun_drop(&self) -> &'static str {
+        "drop\n"
+    }
+
+    fn create_function(&self, fname: &str, body: String) -> String {
+        format!("fun {fname}\n{body}ret\n")
+    }
+
+    fn user_function(&self, fname: &str) -> String {
+        format!("call {fname}\n")
+    }
+
+    fn push_integer(&self, i: &str) -> String {
+        format!("push {i}\n")
+    }
+
+    // hex keeps whatever the string holds on one line
+    fn push_string(&self, s: &str) -> String {
+        let mut bytes = string_bytes(s);
+        bytes.pop(); // the vm knows how long its strings are
+        let hex: String = bytes.iter().map(|b| format!("{b:02x}")).collect();
+        format!("str {hex}\n")
+    }
+
+    fn push_true(&self) -> &'static str {
+        "push 1\n"
+    }
+
+    fn push_false(&self) -> &'static str {
+        "push 0\n"
+    }
+}
@@ -0,0 +1,189 @@
+;; Copyright (C) 2025 Filip Chovanec
+;;
+;; This program is free software: you can redistribute it and/or modify
+;; it under the terms of the GNU General Public License as published by
+;; the Free Software Foundation, either version 3 of the License, or
+;; (at your option) any later version.
+;;
+;; This program is distributed in the hope that it will be useful,
+;; but WITHOUT ANY WARRANTY; without even the implied warranty of
+;; MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+;; GNU General Public License for more details.
+;;
+;; You should have received a copy of the GNU General Public License
+;; along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+;; memory and $sptr are declared by the backend, once it knows how much room the string literals need
+
+(func $dryft_push (export "dryft_push") (param $x i64)
+    global.get $sptr
+    local.get $x
+    i64.store
+    global.get $sptr
+    i32.const 8
+    i32.add
+    global.set $sptr)
+
+(func $dryft_pop (export "dryft_pop") (result i64)
+    global.get $sptr
+    i32.const 8
+    i32.sub
+    global.set $sptr
+    global.get $sptr
+    i64.load)
+
+(func $builtin_copy (local $a i64)
+    call $dryft_pop
+    local.set $a
+    local.get $a
+    call $dryft_push
+    local.get $a
+    call $dryft_push)
+
+(func $builtin_swap (local $b i64) (local $a i64)
+    call $dryft_pop
+    local.set $b
+    call $dryft_pop
+    local.set $a
+    local.get $b
+    call $dryft_push
+    local.get $a
+    call $dryft_push)
+
+(func $builtin_add (local $b i64)
+    call $dryft_pop
+    local.set $b
+    call $dryft_pop
+    local.get $b
+    i64.add
+    call $dryft_push)
+
+(func $builtin_sub (local $b i64)
+    call $dryft_pop
+    local.set $b
+    call $dryft_pop
+    local.get $b
+    i64.sub
+    call $dryft_push)
+
+(func $builtin_mul (local $b i64)
+    call $dryft_pop
+    local.set $b
+    call $dryft_pop
+    local.get $b
+    i64.mul
+    call $dryft_push)
+
+(func $builtin_div (local $b i64)
+    call $dryft_pop
+    local.set $b
+    call $dryft_pop
+    local.get $b
+    i64.div_s
+    call $dryft_push)
+
+(func $builtin_mod (local $b i64)
+    call $dryft_pop
+    local.set $b
+    call $dryft_pop
+    local.get $b
+    i64.rem_s
+    call $dryft_push)
+
+(func $builtin_xor (local $b i64)
+    call $dryft_pop
+    local.set $b
+    call $dryft_pop
+    local.get $b
+    i64.xor
+    call $dryft_push)
+
+(func $builtin_simple_equality (local $b i64)
+    call $dryft_pop
+    local.set $b
+    call $dryft_pop
+    local.get $b
+    i64.eq
+    i64.extend_i32_u
+    call $dryft_push)
+
+(func $builtin_simple_non_equality (local $b i64)
+    call $dryft_pop
+    local.set $b
+    call $dryft_pop
+    local.get $b
+    i64.ne
+    i64.extend_i32_u
+    call $dryft_push)
+
+(func $builtin_num_greater (local $b i64)
+    call $dryft_pop
+    local.set $b
+    call $dryft_pop
+    local.get $b
+    i64.gt_s
+    i64.extend_i32_u
+    call $dryft_push)
+
+(func $builtin_num_greater_or_equal (local $b i64)
+    call $dryft_pop
+    local.set $b
+    call $dryft_pop
+    local.get $b
+    i64.ge_s
+    i64.extend_i32_u
+    call $dryft_push)
+
+(func $builtin_num_less_than (local $b i64)
+    call $dryft_pop
+    local.set $b
+    call $dryft_pop
+    local.get $b
+    i64.lt_s
+    i64.extend_i32_u
+    call $dryft_push)
+
+(func $builtin_num_less_than_or_equal (local $b i64)
+    call $dryft_pop
+    local.set $b
+    call $dryft_pop
+    local.get $b
+    i64.le_s
+    i64.extend_i32_u
+    call $dryft_push)
+
+(func $builtin_logical_not
+    call $dryft_pop
+    i64.eqz
+    i64.extend_i32_u
+    call $dryft_push)
+
+(func $builtin_logical_and (local $b i64)
+    call $dryft_pop
+    local.set $b
+    call $dryft_pop
+    i64.const 0
+    i64.ne
+    local.get $b
+    i64.const 0
+    i64.ne
+    i32.and
+    i64.extend_i32_u
+    call $dryft_push)
+
+(func $builtin_logical_or (local $b i64)
+    call $dryft_pop
+    local.set $b
+    call $dryft_pop
+    i64.const 0
+    i64.ne
+    local.get $b
+    i64.const 0
+    i64.ne
+    i32.or
+    i64.extend_i32_u
+    call $dryft_push)
+
+(func (export "main")
+    call $fun_main)
+
@@ -0,0 +1,231 @@
+/*
+* Copyright (C) 2025 Filip Chovanec
+*
+* This program is free software: you can redistribute it and/or modify
+* it under the terms of the GNU General Public License as published by
+* the Free Software Foundation, either version 3 of the License, or
+* (at your option) any later version.
+*
+* This program is distributed in the hope that it will be useful,
+* but WITHOUT ANY WARRANTY; without even the implied warranty of
+* MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+* GNU General Public License for more details.
+*
+* You should have received a copy of the GNU General Public License
+* along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+// WebAssembly text format, the data stack lives in linear memory
+// linked in symbols become imports from "env", the host reaches the data stack through the exported memory and dryft_pop/dryft_push
+
+use crate::backends::{hex_string, split_body, string_bytes, Backend, Labels, LOOP_END, WHEN_END};
+use std::cell::Cell;
+
+// lines that have to be moved out of function bodies
+const HOIST: &str = ";; hoist ";
+const VAR_MARKER: &str = ";; var ";
+
+// string literals are placed from here on, the data stack starts on the page after the last one
+const DATA_START: usize = 16;
+const PAGE: usize = 65536;
+
+pub struct WatBackend {
+    labels: Labels,
+    data_end: Cell<usize>, // first free byte after the string literals
+}
+
+impl Default for WatBackend {
+    fn default() -> Self {
+        Self {
+            labels: Labels::default(),
+            data_end: Cell::new(DATA_START),
+        }
+    }
+}
+
+impl Backend for WatBackend {
+    fn fun_exclusive_or(&self) -> &'static str {
+        "    call $builtin_xor\n"
+    }
+
+    fn create_elect_block(&self, body: String) -> String {
+        let end = format!("$when_{}", self.labels.next());
+        format!("    block {end}\n{}    end\n", body.replace(WHEN_END, &end))
+    }
+
+    fn method_return(&self) -> String {
+        "    return\n".to_string()
+    }
+
+    fn create_conditional_statement(&self, body: String, inelect: bool) -> String {
+        let skip = if inelect {
+            format!("    br {WHEN_END}\n")
+        } else {
+            String::new()
+        };
+        format!("    call $dryft_pop\n    i64.const 0\n    i64.ne\n    if\n{body}{skip}    end\n")
+    }
+
+    fn fun_num_less_than_or_equal(&self) -> &'static str {
+        "    call $builtin_num_less_than_or_equal\n"
+    }
+
+    fn fun_num_less_than(&self) -> &'static str {
+        "    call $builtin_num_less_than\n"
+    }
+
+    fn fun_num_greater_or_equal(&self) -> &'static str {
+        "    call $builtin_num_greater_or_equal\n"
+    }
+
+    fn write_variable(&self, name: &str) -> String {
+        format!("    call $dryft_pop\n    local.set $var_{name}\n")
+    }
+
+    fn read_variable(&self, name: &str) -> String {
+        format!("    local.get $var_{name}\n    call $dryft_push\n")
+    }
+
+    fn create_variable(&self, name: &str) -> String {
+        format!("{VAR_MARKER}{name}\n{}", self.write_variable(name))
+    }
+
+    fn loop_break(&self) -> String {
+        format!("    br {LOOP_END}\n")
+    }
+
+    fn fun_num_greater(&self) -> &'static str {
+        "    call $builtin_num_greater\n"
+    }
+
+    fn fun_logical_not(&self) -> &'static str {
+        "    call $builtin_logical_not\n"
+    }
+
+    fn fun_logical_and(&self) -> &'static str {
+        "    call $builtin_logical_and\n"
+    }
+
+    fn fun_logical_or(&self) -> &'static str {
+        "    call $builtin_logical_or\n"
+    }
+
+    fn create_loop_block(&self, body: String) -> String {
+        let n = self.labels.next();
+        format!(
+            "    block $loop_end_{n}\n    loop $loop_{n}\n{}    br $loop_{n}\n    end\n    end\n",
+            body.replace(LOOP_END, &format!("$loop_end_{n}"))
+        )
+    }
+
+    // imports have to come before any definition in the module
+    fn complete(&self, compiled: &str) -> String {
+        let mut imports = String::new();
+        let mut rest = String::new();
+        for line in compiled.lines() {
+            let target = if line.starts_with("(import") {
+                &mut imports
+            } else {
+                &mut rest
+            };
+            target.push_str(line);
+            target.push('\n');
+        }
+        // the data stack gets a page of its own
+        let stack = self.data_end.get().div_ceil(PAGE).max(1) * PAGE;
+        let memory = format!(
+            "(memory (export \"memory\") {})\n(global $sptr (mut i32) (i32.const {stack}))\n",
+            stack / PAGE + 1
+        );
+        format!(
+            "(module\n{imports}{memory}{}{rest})\n",
+            include_str!("base.wat")
+        )
+    }
+
+    fn fun_simple_equality(&self) -> &'static str {
+        "    call $builtin_simple_equality\n"
+    }
+
+    fn fun_simple_non_equality(&self) -> &'static str {
+        "    call $builtin_simple_non_equality\n"
+    }
+
+    fn fun_swap(&self) -> &'static str {
+        "    call $builtin_swap\n"
+    }
+
+    fn linkin_function(&self, name: &str) -> String {
+        format!(
+            "(import \"env\" \"{name}\" (func ${name}))\n(func $fun_{name}\n    call ${name})\n\n"
+        )
+    }
+
+    fn fun_add(&self) -> &'static str {
+        "    call $builtin_add\n"
+    }
+
+    fn fun_sub(&self) -> &'static str {
+        "    call $builtin_sub\n"
+    }
+
+    fn fun_mul(&self) -> &'static str {
+        "    call $builtin_mul\n"
+    }
+
+    fn fun_div(&self) -> &'static str {
+        "    call $builtin_div\n"
+    }
+
+    fn fun_mod(&self) -> &'static str {
+        "    call $builtin_mod\n"
+    }
+
+    fn fun_copy(&self) -> &'static str {
+        "    call $builtin_copy\n"
+    }
+
+    fn fun_drop(&self) -> &'static str {
+        "    call $dryft_pop\n    drop\n"
+    }
+
+    // data segments go before the function, variables become locals
+    fn create_function(&self, fname: &str, body: String) -> String {
+        let body = split_body(&body, Some(HOIST), VAR_MARKER);
+
+        let mut out = body.hoisted;
+        out.push_str(&format!("(func $fun_{fname}\n"));
+        for v in body.vars.iter() {
+            out.push_str(&format!("    (local $var_{v} i64)\n"));
+        }
+        out.push_str(&body.code);
+        out.push_str(")\n\n");
+        out
+    }
+
+    fn user_function(&self, fname: &str) -> String {
+        format!("    call $fun_{fname}\n")
+    }
+
+    fn push_integer(&self, i: &str) -> String {
+        format!("    i64.const {i}\n    call $dryft_push\n")
+    }
+
+    fn push_string(&self, s: &str) -> String {
+        let bytes = string_bytes(s);
+        let address = self.data_end.get();
+        self.data_end.set(address + bytes.len());
+        format!(
+            "{HOIST}(data (i32.const {address}) \"{}\")\n    i64.const {address}\n    call $dryft_push\n",
+            hex_string(&bytes, false)
+        )
+    }
+
+    fn push_true(&self) -> &'static str {
+        "    i64.const 1\n    call $dryft_push\n"
+    }
+
+    fn push_false(&self) -> &'static str {
+        "    i64.const 0\n    call $dryft_push\n"
+    }
+}
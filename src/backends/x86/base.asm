@@ -29,7 +29,7 @@ section .text
 
 ; input => rdi
 ; uses rax rdi
-extern dryft_push
+global dryft_push
 dryft_push:
     mov rax, [sptr]
     mov [rax], rdi
@@ -43,7 +43,7 @@ dryft_push:
 
 ; rax => output
 ; uses rax
-extern dryft_pop
+global dryft_pop
 dryft_pop: 
     mov rax, [sptr]
     sub qword [sptr], ITEM_SIZE
@@ -143,6 +143,69 @@ builtin_simple_non_equality:
     mpush 1
     ret
 
+; comparisons push 1 or 0, %1 is the setcc condition
+%macro compare_top 1
+    call dryft_pop
+    mov rbx, rax
+    call dryft_pop
+    cmp rax, rbx
+    set%1 al
+    movzx rax, al
+    mpush rax
+    ret
+%endmacro
+
+builtin_num_greater:
+    compare_top g
+
+builtin_num_greater_or_equal:
+    compare_top ge
+
+builtin_num_less_than:
+    compare_top l
+
+builtin_num_less_than_or_equal:
+    compare_top le
+
+builtin_logical_not:
+    call dryft_pop
+    test rax, rax
+    setz al
+    movzx rax, al
+    mpush rax
+    ret
+
+builtin_logical_and:
+    call dryft_pop
+    mov rbx, rax
+    call dryft_pop
+    test rax, rax
+    setnz al
+    test rbx, rbx
+    setnz bl
+    and al, bl
+    movzx rax, al
+    mpush rax
+    ret
+
+builtin_logical_or:
+    call dryft_pop
+    mov rbx, rax
+    call dryft_pop
+    or rax, rbx
+    setnz al
+    movzx rax, al
+    mpush rax
+    ret
+
+builtin_xor:
+    call dryft_pop
+    mov rbx, rax
+    call dryft_pop
+    xor rax, rbx
+    mpush rax
+    ret
+
 
 SYSCALL_WRITE equ 1
 SYSCALL_EXIT equ 60
@@ -174,16 +237,21 @@ EXIT_OK equ 0
 ;    linux_syscall SYSCALL_WRITE, CODE_STDOUT, msgbuf, 2
 ;    ret
 
-; we can not use _start since we need to link with gcc
+; we can not use _start since we need to link with gcc, main() in native/stdc calls us
 
 global dryft_main
 
 dryft_main:
+    push rbx ; callee saved in the C ABI, but our builtins use it, this also aligns the stack
     ; stack init
     lea rax, [rel stack]
     mov [sptr], rax
 
     call fun_main
 
-    linux_syscall SYSCALL_EXIT, EXIT_OK, 0, 0
+    pop rbx
+    ret
+
+section .note.GNU-stack noalloc noexec nowrite progbits
+section .text
 
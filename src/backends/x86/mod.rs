@@ -15,61 +15,101 @@
 * along with this program.  If not, see <https://www.gnu.org/licenses/>.
 */
 
-// last working commit: 009e79bc974da89b12e591e17a35e9e99c8fe759
-// TODO: fix all todo!() macros
-// TODO: fix stdlib functions not working when linked in?
+// NASM flavoured x86-64 assembly for linux, linked against native/stdc with gcc
+// the data stack lives in .bss, dryft code keeps nothing in registers between words
 
-use crate::backends::Backend;
+use crate::backends::{split_body, string_bytes, Backend, Labels, LOOP_END, METHOD_END, WHEN_END};
 
-pub struct Nasm64Backend {}
+// marks where a variable gets a slot in the stack frame of the enclosing method
+const VAR_MARKER: &str = "\t; var ";
+
+#[derive(Default)]
+pub struct Nasm64Backend {
+    labels: Labels,
+}
+
+impl Nasm64Backend {
+    // labels have to be unique in the whole file
+    fn label(&self) -> String {
+        format!("__dryft_{}", self.labels.next())
+    }
+}
 
 impl Backend for Nasm64Backend {
+    fn fun_exclusive_or(&self) -> &'static str {
+        "\tcall builtin_xor\n"
+    }
+
+    fn create_elect_block(&self, body: String) -> String {
+        let end = self.label();
+        format!("{}{end}:\n", body.replace(WHEN_END, &end))
+    }
+
+    fn method_return(&self) -> String {
+        format!("\tjmp {METHOD_END}\n")
+    }
+
+    fn create_conditional_statement(&self, body: String, inelect: bool) -> String {
+        let skip = self.label();
+        let leave = if inelect {
+            format!("\tjmp {WHEN_END}\n")
+        } else {
+            String::new()
+        };
+        format!("\tcall dryft_pop\n\ttest rax, rax\n\tjz {skip}\n{body}{leave}{skip}:\n")
+    }
+
     fn fun_num_less_than_or_equal(&self) -> &'static str {
-        todo!()
+        "\tcall builtin_num_less_than_or_equal\n"
     }
 
     fn fun_num_less_than(&self) -> &'static str {
-        todo!()
+        "\tcall builtin_num_less_than\n"
     }
 
     fn fun_num_greater_or_equal(&self) -> &'static str {
-        todo!()
+        "\tcall builtin_num_greater_or_equal\n"
     }
 
-    fn write_variable(&self, _name: &str) -> String {
-        todo!()
+    fn write_variable(&self, name: &str) -> String {
+        format!("\tcall dryft_pop\n\tmov [var_{name}], rax\n")
     }
 
-    fn read_variable(&self, _name: &str) -> String {
-        todo!()
+    fn read_variable(&self, name: &str) -> String {
+        format!("\tmpush [var_{name}]\n")
     }
 
-    fn create_variable(&self, _name: &str) -> String {
-        todo!()
+    fn create_variable(&self, name: &str) -> String {
+        format!("{VAR_MARKER}{name}\n{}", self.write_variable(name))
     }
 
     fn loop_break(&self) -> String {
-        todo!()
+        format!("\tjmp {LOOP_END}\n")
     }
 
     fn fun_num_greater(&self) -> &'static str {
-        todo!()
+        "\tcall builtin_num_greater\n"
     }
 
     fn fun_logical_not(&self) -> &'static str {
-        todo!()
+        "\tcall builtin_logical_not\n"
     }
 
     fn fun_logical_and(&self) -> &'static str {
-        todo!()
+        "\tcall builtin_logical_and\n"
     }
 
     fn fun_logical_or(&self) -> &'static str {
-        todo!()
+        "\tcall builtin_logical_or\n"
     }
 
-    fn create_loop_block(&self, _body: String) -> String {
-        todo!()
+    fn create_loop_block(&self, body: String) -> String {
+        let start = self.label();
+        let end = self.label();
+        format!(
+            "{start}:\n{}\tjmp {start}\n{end}:\n",
+            body.replace(LOOP_END, &end)
+        )
     }
 
     fn complete(&self, compiled: &str) -> String {
@@ -79,19 +119,22 @@ impl Backend for Nasm64Backend {
     }
 
     fn fun_simple_equality(&self) -> &'static str {
-        "\t call builtin_simple_equality\n"
+        "\tcall builtin_simple_equality\n"
     }
 
     fn fun_simple_non_equality(&self) -> &'static str {
-        "\t call builtin_simple_non_equality\n"
+        "\tcall builtin_simple_non_equality\n"
     }
 
     fn fun_swap(&self) -> &'static str {
-        "\t call data_swap\n"
+        "\tcall data_swap\n"
     }
 
     fn linkin_function(&self, name: &str) -> String {
-        format!("\nextern {name}\nfun_{name}:\n\tcall {name}\n\tret\n\n")
+        // our calls do not keep the stack aligned the way the C ABI wants it
+        format!(
+            "\nextern {name}\nfun_{name}:\n\tpush rbp\n\tmov rbp, rsp\n\tand rsp, -16\n\tcall {name}\n\tleave\n\tret\n\n"
+        )
     }
 
     fn fun_add(&self) -> &'static str {
@@ -122,8 +165,30 @@ impl Backend for Nasm64Backend {
         "\tcall dryft_pop\n"
     }
 
+    // variables live below rbp, each gets its own qword slot named by a %define
     fn create_function(&self, fname: &str, body: String) -> String {
-        format!("fun_{fname}:\n{body}\tret\n\n")
+        let vars = split_body(&body, None, VAR_MARKER).vars;
+
+        let mut out = String::new();
+        for (i, v) in vars.iter().enumerate() {
+            out.push_str(&format!("%define var_{v} rbp - {}\n", 8 * (i + 1)));
+        }
+
+        let end = self.label();
+        // keep rsp 16 byte aligned
+        let frame = (vars.len() * 8).div_ceil(16) * 16;
+        out.push_str(&format!("fun_{fname}:\n\tpush rbp\n\tmov rbp, rsp\n"));
+        if frame > 0 {
+            out.push_str(&format!("\tsub rsp, {frame}\n"));
+        }
+        out.push_str(&body.replace(METHOD_END, &end));
+        out.push_str(&format!("{end}:\n\tleave\n\tret\n"));
+
+        for v in vars.iter() {
+            out.push_str(&format!("%undef var_{v}\n"));
+        }
+        out.push('\n');
+        out
     }
 
     fn user_function(&self, fname: &str) -> String {
@@ -134,7 +199,21 @@ impl Backend for Nasm64Backend {
         format!("\tmpush {i}\n")
     }
 
-    fn push_string(&self, _s: &str) -> String {
-        todo!()
+    // literals go to .data as bytes, so nothing the user wrote ends up as assembly text
+    fn push_string(&self, s: &str) -> String {
+        let label = self.label();
+        let bytes: Vec<String> = string_bytes(s).iter().map(|b| b.to_string()).collect();
+        format!(
+            "section .data\n{label}: db {}\nsection .text\n\tlea rdi, [rel {label}]\n\tcall dryft_push\n",
+            bytes.join(", ")
+        )
+    }
+
+    fn push_true(&self) -> &'static str {
+        "\tmpush 1\n"
+    }
+
+    fn push_false(&self) -> &'static str {
+        "\tmpush 0\n"
     }
 }
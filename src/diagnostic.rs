@@ -0,0 +1,145 @@
+/*
+* Copyright (C) 2025 Filip Chovanec
+*
+* This program is free software: you can redistribute it and/or modify
+* it under the terms of the GNU General Public License as published by
+* the Free Software Foundation, either version 3 of the License, or
+* (at your option) any later version.
+*
+* This program is distributed in the hope that it will be useful,
+* but WITHOUT ANY WARRANTY; without even the implied warranty of
+* MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+* GNU General Public License for more details.
+*
+* You should have received a copy of the GNU General Public License
+* along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+// region of source code a token was read from, lines and columns start at 1
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Span {
+    pub file: String,
+    pub start_line: isize,
+    pub start_column: isize,
+    pub end_line: isize,
+    pub end_column: isize, // inclusive
+    pub start: usize,      // byte range in the file, end exclusive
+    pub end: usize,
+}
+
+impl Default for Span {
+    fn default() -> Self {
+        Self {
+            file: "<main>".to_string(),
+            start_line: 1,
+            start_column: 1,
+            end_line: 1,
+            end_column: 1,
+            start: 0,
+            end: 0,
+        }
+    }
+}
+
+// everything the compiler has to say about a piece of source code
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub span: Span,
+    pub word: isize, // index of the word on its line
+    pub message: String,
+    pub notes: Vec<String>,
+    pub snippet: Option<String>, // source line the span starts on, if we have it
+}
+
+impl Diagnostic {
+    // src is the whole file the span points into
+    pub fn new(severity: Severity, span: Span, word: isize, msg: &str, src: &str) -> Self {
+        let snippet = src
+            .lines()
+            .nth(span.start_line as usize - 1)
+            .map(String::from);
+        Self {
+            severity,
+            span,
+            word,
+            message: msg.to_string(),
+            notes: vec![],
+            snippet,
+        }
+    }
+
+    pub fn with_note(mut self, note: &str) -> Self {
+        self.notes.push(note.to_string());
+        self
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+
+    // multi-line rustc-style report, with the offending source underlined
+    pub fn render(&self) -> String {
+        let kind = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        let span = &self.span;
+        let mut out = format!("{kind}: {}\n", self.message);
+
+        let line = span.start_line.to_string();
+        let gutter = " ".repeat(line.len());
+        out.push_str(&format!(
+            "{gutter}--> {}:{}:{}\n",
+            span.file, span.start_line, span.start_column
+        ));
+
+        if let Some(snippet) = &self.snippet {
+            let width = if span.end_line == span.start_line {
+                (span.end_column - span.start_column + 1).max(1) as usize
+            } else {
+                snippet.chars().count() + 1 - span.start_column as usize
+            };
+            // keep tabs so the carets line up with the source
+            let indent: String = snippet
+                .chars()
+                .take(span.start_column as usize - 1)
+                .map(|c| if c == '\t' { '\t' } else { ' ' })
+                .collect();
+            out.push_str(&format!("{gutter} |\n"));
+            out.push_str(&format!("{line} | {snippet}\n"));
+            out.push_str(&format!("{gutter} | {indent}{}\n", "^".repeat(width)));
+        }
+
+        for note in self.notes.iter() {
+            out.push_str(&format!("{gutter} = note: {note}\n"));
+        }
+        out
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let kind = match self.severity {
+            Severity::Error => "ERROR",
+            Severity::Warning => "WARNING",
+        };
+        write!(
+            f,
+            "[DRYFT {kind}] {}:{}, word {}: {}",
+            self.span.file, self.span.start_line, self.word, self.message
+        )?;
+        for note in self.notes.iter() {
+            write!(f, "\n\tnote: {note}")?;
+        }
+        Ok(())
+    }
+}
@@ -15,580 +15,668 @@
 * along with this program.  If not, see <https://www.gnu.org/licenses/>.
 */
 
+use crate::ast::{Block, Definition, Literal, MethodDef, Node, NodeKind};
 use crate::backends::Backend;
+use crate::diagnostic::{Diagnostic, Span};
+use crate::parser::Parser;
+use crate::state::CompileOptions;
 use crate::state::CompileState;
 use crate::state::DefinitionTypes;
 use crate::state::Method;
 use crate::state::MethodClass;
+use crate::state::TypeCheck;
 use crate::state::ValueTypes;
-use regex::Regex;
-use std::fs;
-
-pub fn compile(backend: &mut Box<dyn Backend>, code: &str) -> CompileState {
-    let mut cs = CompileState::new();
-
-    macro_rules! new_token {
-        () => {{
-            if !cs.word.is_empty() {
-                cs.tokenumber += 1;
-                cs.log_tokens.push(cs.word.clone());
-                handle_token(backend, &mut cs);
-                cs.word = String::new();
-            }
-        }};
-    }
-
-    let mut code = code.to_string();
-
-    // drain the whole text buffer character ny character
-    // compilation is single-pass
-    while !code.is_empty() {
-        if !cs.prepend.is_empty() {
-            code.insert_str(0, &cs.prepend);
-            cs.prepend = String::new();
-        }
 
-        let letter = code.remove(0);
-
-        if letter == '\n' {
-            cs.linenumber += 1;
-            cs.tokenumber = 0;
-        }
+pub fn compile(
+    backend: &mut Box<dyn Backend>,
+    code: &str,
+) -> Result<CompileState, Vec<Diagnostic>> {
+    compile_with(backend, code, CompileOptions::default())
+}
 
-        // Track line and file where current token starts
-        if cs.word.is_empty() && !matches!(letter, ' ' | '\n' | '\t' | '#' | '"') {
-            cs.token_line = cs.linenumber;
-            cs.token_file = cs.current_file.clone();
-        }
+pub fn compile_with(
+    backend: &mut Box<dyn Backend>,
+    code: &str,
+    options: CompileOptions,
+) -> Result<CompileState, Vec<Diagnostic>> {
+    compile_file(backend, "<main>", code, options)
+}
 
-        match letter {
-            c if cs.iscomment => {
-                if c == '#' {
-                    cs.iscomment = false;
-                }
-            }
-            c if cs.isstring => {
-                if c == '"' {
-                    cs.isstring = false;
-                    cs.add2body(&backend.push_string(&cs.newstring));
-                    cs.push_type(ValueTypes::Text);
-                    cs.newstring = String::new();
-                } else {
-                    cs.newstring.push(c);
-                }
-            }
-            c if cs.isannotation => {
-                if c == ')' {
-                    cs.isannotation = false;
-                } else {
-                    cs.annotation_content.push(c);
-                }
-            }
-            ' ' | '\n' | '\t' => new_token!(),
-            '#' => cs.iscomment = true,
-            '"' => cs.isstring = true,
-            '(' => cs.isannotation = true,
-            other => cs.word.push(other),
-        }
-
-        // Track when we've finished processing an included file
-        if cs.prepend_remaining > 0 {
-            cs.prepend_remaining -= 1;
-            if cs.prepend_remaining == 0 && !cs.file_stack.is_empty() {
-                let (prev_file, prev_line) = cs.file_stack.pop().unwrap();
-                cs.current_file = prev_file;
-                cs.linenumber = prev_line;
-                cs.tokenumber = 0;
-                // Don't reset token_line here - it will be updated naturally when next token starts
-            }
-        }
+// on failure, returns every diagnostic collected in the whole file, warnings included
+// file is what diagnostics call the code, includes are looked up next to it first
+pub fn compile_file(
+    backend: &mut Box<dyn Backend>,
+    file: &str,
+    code: &str,
+    options: CompileOptions,
+) -> Result<CompileState, Vec<Diagnostic>> {
+    let mut cs = CompileState::with_options(options);
+
+    let mut parser = Parser::new(file, code).map_err(|e| vec![e])?;
+    parser.include_paths = cs.options.include_paths.clone();
+    let program = parser.program();
+    cs.sources = parser.sources;
+    cs.log_tokens = parser.log_tokens;
+    cs.diagnostics = parser.diagnostics;
+
+    // code generation is single-pass, a method can only use what was defined before it
+    gen_definitions(backend, &mut cs, &program.definitions);
+
+    // parsing and codegen report separately, so put everything back in source order
+    cs.diagnostics
+        .sort_by_key(|d| (d.span.file.clone(), d.span.start));
+
+    if cs.diagnostics.iter().any(|d| d.is_error()) {
+        return Err(cs.diagnostics);
     }
-    new_token!(); // last word may not be whitespace separated
+
     cs.out = Some(cs.bodystack.remove(0));
 
-    cs
+    Ok(cs)
 }
 
-pub fn compile_full(mut backend: Box<dyn Backend>, code: &str) -> String {
-    let built = compile(&mut backend, code)
+pub fn compile_full(mut backend: Box<dyn Backend>, code: &str) -> Result<String, Vec<Diagnostic>> {
+    let built = compile(&mut backend, code)?
         .out
         .expect("No code compiled :(");
-    backend.complete(&built)
+    Ok(backend.complete(&built))
 }
 
-fn handle_token(backend: &mut Box<dyn Backend>, cs: &mut CompileState) {
-    let regexint = Regex::new(r"^-?\d+$").unwrap();
+// compile one REPL line on top of everything compiled into cs before
+// definitions are registered, the rest becomes the method called name, which runs on what earlier lines left on the stack
+// returns the code for this line only, warnings stay in cs.diagnostics
+pub fn compile_line(
+    backend: &mut Box<dyn Backend>,
+    cs: &mut CompileState,
+    code: &str,
+    name: &str,
+) -> Result<String, Vec<Diagnostic>> {
+    // the types of the persistent stack live in the root frame
+    if cs.typestack.is_empty() {
+        cs.grow_typestack();
+        cs.grow_voidstack();
+    }
+    cs.diagnostics.clear();
 
-    // this should actually only be used for defintions that need their own body and meta stack :C, allocating a new body is unnecessary otherwise
-    macro_rules! new_definition {
-        ($variant:ident) => {{
-            cs.defnstack.push(DefinitionTypes::$variant);
-            cs.metastack.push(vec![]);
-            cs.bodystack.push(String::new());
-        }};
+    let mut parser = Parser::new("<repl>", code).map_err(|e| vec![e])?;
+    parser.include_paths = cs.options.include_paths.clone();
+    let (definitions, nodes) = parser.line();
+    cs.sources.extend(parser.sources);
+    cs.log_tokens.append(&mut parser.log_tokens);
+    cs.diagnostics.append(&mut parser.diagnostics);
+
+    gen_definitions(backend, cs, &definitions);
+    if !nodes.is_empty() {
+        if let Err(e) = gen_toplevel(backend, cs, &nodes, name) {
+            cs.diagnostics.push(e);
+        }
     }
 
-    macro_rules! add_method {
-        ($variant:ident) => {
-            let meta = cs.metastack.pop().expect("no meta");
-            let body = cs.bodystack.pop().expect("no body");
-            let ts = cs.typestack.pop().expect("no type");
-            let vs = cs.voidstack.pop().expect("no void");
+    cs.diagnostics
+        .sort_by_key(|d| (d.span.file.clone(), d.span.start));
+    if cs.diagnostics.iter().any(|d| d.is_error()) {
+        return Err(cs.diagnostics.clone());
+    }
+    Ok(std::mem::take(&mut cs.bodystack[0]))
+}
 
-            let class = crate::state::MethodClass::$variant;
+// like an act body, but variables go to the root scope and types stay on the root frame
+fn gen_toplevel(
+    backend: &mut Box<dyn Backend>,
+    cs: &mut CompileState,
+    nodes: &[Node],
+    name: &str,
+) -> Result<(), Diagnostic> {
+    cs.defnstack.push(DefinitionTypes::Action);
+    cs.grow_bodystack();
+    cs.method_scope = 0;
+
+    gen_nodes(backend, cs, nodes)?;
+
+    cs.defnstack.pop();
+    let body = cs.bodystack.pop().unwrap();
+    let f = backend.create_function(name, body);
+    cs.add2body(&f);
+    Ok(())
+}
 
-            let fname = meta
-                .get(0)
-                .unwrap_or_else(|| cs.throw_error("No method name provided"));
+// errors do not stop compilation, we just skip the broken definition and keep going
+fn gen_definitions(backend: &mut Box<dyn Backend>, cs: &mut CompileState, defs: &[Definition]) {
+    for def in defs {
+        let depth = cs.depth();
+        if let Err(e) = gen_definition(backend, cs, def) {
+            cs.diagnostics.push(e);
+            cs.restore(depth);
+            // so its uses are not reported as unknown tokens on top of the real error
+            match def {
+                Definition::Fun(m) => stand_in(cs, m, MethodClass::Function),
+                Definition::Act(m) => stand_in(cs, m, MethodClass::Action),
+                _ => {}
+            }
+        }
+    }
+}
 
-            // if fname == "main" {
-            //     cs.throw_error("main must be defined as an action")
-            // }
+// a broken method keeps its name, with the stack effect it declared or none at all
+fn stand_in(cs: &mut CompileState, m: &MethodDef, class: MethodClass) {
+    if cs.methods.contains_key(&m.name) {
+        return;
+    }
+    let (itypes, etypes) = match &m.signature {
+        Some(sig) => {
+            let (inputs, outputs) = cs.instantiate(&sig.inputs, &sig.outputs);
+            (inputs.into_iter().rev().collect(), outputs)
+        }
+        None => (vec![], vec![]),
+    };
+    cs.methods.insert(
+        m.name.clone(),
+        Method {
+            name: m.name.clone(),
+            code: String::new(),
+            class,
+            itypes,
+            etypes,
+            declared: m.signature.is_some(),
+            pure: class == MethodClass::Function,
+            span: m.token.span.clone(),
+            source: m.name.clone(),
+        },
+    );
+}
 
-            cs.varscopes.pop();
+fn gen_definition(
+    backend: &mut Box<dyn Backend>,
+    cs: &mut CompileState,
+    def: &Definition,
+) -> Result<(), Diagnostic> {
+    match def {
+        Definition::Fun(m) => gen_method(backend, cs, m, MethodClass::Function),
+        Definition::Act(m) => gen_method(backend, cs, m, MethodClass::Action),
+        Definition::Linkin(l) => {
+            cs.locate(&l.token);
+            // without a declared stack effect, the typechecker assumes the symbol does not touch the stack
+            let (itypes, etypes) = match &l.signature {
+                Some(sig) => {
+                    let (inputs, outputs) = cs.instantiate(&sig.inputs, &sig.outputs);
+                    (inputs.into_iter().rev().collect(), outputs)
+                }
+                None => (vec![], vec![]),
+            };
             cs.methods.insert(
-                fname.clone(),
+                l.name.clone(),
                 Method {
-                    name: fname.clone(),
-                    code: body.clone(),
-                    class: class,
-                    itypes: vs,
-                    etypes: ts,
+                    name: l.name.clone(),
+                    code: "LINKED IN".to_string(),
+                    class: l.class,
+                    itypes,
+                    etypes,
+                    declared: l.signature.is_some(),
+                    // we can not look inside linked in symbols, a linkin fun is trusted to be pure
+                    pure: l.class == MethodClass::Function,
+                    span: l.token.span.clone(),
+                    source: l.name.clone(),
                 },
             );
-
-            let f = backend.create_function(fname.as_ref(), body);
-            cs.add2body(&f);
-        };
+            cs.add2body(&backend.linkin_function(&l.name));
+            Ok(())
+        }
+        Definition::Module(defs) => {
+            cs.defnstack.push(DefinitionTypes::Module);
+            cs.grow_bodystack();
+            gen_definitions(backend, cs, defs);
+            let _body = cs.bodystack.pop().unwrap();
+            cs.defnstack.pop();
+            Ok(())
+        }
     }
+}
 
-    macro_rules! add_then_block {
-        () => {
-            let body = cs.bodystack.pop().unwrap();
-            cs.varscopes.pop();
-
-            cs.expect_no_type_footprint();
-
-            let inelect = cs.defnstack.last().unwrap() == &DefinitionTypes::Elect;
-
-            cs.add2body(&backend.create_conditional_statement(body, inelect));
-        };
+fn gen_method(
+    backend: &mut Box<dyn Backend>,
+    cs: &mut CompileState,
+    m: &MethodDef,
+    class: MethodClass,
+) -> Result<(), Diagnostic> {
+    cs.locate(&m.token);
+
+    // if m.name == "main" && class == MethodClass::Function {
+    //     return Err(cs.error("main must be defined as an action"));
+    // }
+
+    cs.defnstack.push(match class {
+        MethodClass::Function => DefinitionTypes::Function,
+        MethodClass::Action => DefinitionTypes::Action,
+    });
+    cs.grow_bodystack();
+    cs.grow_varscopes();
+    cs.grow_typestack();
+    cs.grow_voidstack();
+    // a broken definition may have left some behind
+    cs.moved.clear();
+    cs.loop_scopes.clear();
+    cs.elect_exits.clear();
+    cs.diverged = false;
+    cs.method_scope = cs.varscopes.len() - 1;
+
+    // a declared signature tells us what is on the stack when the body starts
+    let declared = m
+        .signature
+        .as_ref()
+        .map(|sig| cs.instantiate(&sig.inputs, &sig.outputs));
+    if let Some((inputs, _)) = &declared {
+        cs.push_types(inputs);
     }
 
-    macro_rules! add_elect_block {
-        () => {
-            let body = cs.bodystack.pop().unwrap();
-            cs.add2body(&backend.create_elect_block(body));
-        };
+    gen_nodes(backend, cs, &m.body.nodes)?;
+    cs.locate(&m.body.end);
+
+    if let (Some(sig), Some((inputs, outputs))) = (&m.signature, &declared) {
+        cs.locate(&m.token);
+        cs.expect_signature(sig, inputs, outputs)?;
+    } else if class == MethodClass::Action && cs.options.typecheck != TypeCheck::Off {
+        // an act without a declared stack effect is not expected to hand out resources
+        let left = cs.typestack.last().unwrap().clone();
+        if let Some(t) = left.iter().find(|t| cs.is_linear(t)) {
+            let t = cs.resolve(t);
+            cs.type_error(&format!(
+                "Linear value of type {t} left on the stack at the end of act {}",
+                m.name
+            ))?;
+        }
     }
+    cs.close_varscope()?;
 
-    macro_rules! add_loop_block {
-        () => {
-            let body = cs.bodystack.pop().unwrap();
-            //cs.varscopes.pop();
-
-            cs.expect_no_type_footprint();
+    cs.defnstack.pop();
+    let body = cs.bodystack.pop().expect("no body");
+    let mut ts = cs.typestack.pop().expect("no type");
+    let mut vs = cs.voidstack.pop().expect("no void");
 
-            cs.add2body(&backend.create_loop_block(body));
-        };
+    // itypes are in the order they get popped, signatures list them bottom to top
+    if let Some((inputs, outputs)) = declared {
+        vs = inputs.into_iter().rev().collect();
+        ts = outputs;
     }
+    // whatever is still a type variable stays polymorphic, see instantiate()
+    vs = vs.iter().map(|t| cs.resolve(t)).collect();
+    ts = ts.iter().map(|t| cs.resolve(t)).collect();
+
+    let span = Span {
+        end_line: m.body.end.span.end_line,
+        end_column: m.body.end.span.end_column,
+        end: m.body.end.span.end,
+        ..m.token.span.clone()
+    };
+    cs.methods.insert(
+        m.name.clone(),
+        Method {
+            name: m.name.clone(),
+            code: body.clone(),
+            class,
+            itypes: vs,
+            etypes: ts,
+            declared: m.signature.is_some(),
+            pure: class == MethodClass::Function, // its body was just checked
+            source: cs
+                .sources
+                .get(&span.file)
+                .and_then(|src| src.get(span.start..span.end))
+                .unwrap_or(&m.name)
+                .to_string(),
+            span,
+        },
+    );
+
+    let f = backend.create_function(&m.name, body);
+    cs.add2body(&f);
+    Ok(())
+}
 
-    macro_rules! add_builtin {
-        ($prop:ident) => {{
-            cs.add2body(backend.$prop())
-        }};
+fn gen_nodes(
+    backend: &mut Box<dyn Backend>,
+    cs: &mut CompileState,
+    nodes: &[Node],
+) -> Result<(), Diagnostic> {
+    for node in nodes {
+        gen_node(backend, cs, node)?;
     }
+    Ok(())
+}
 
-    macro_rules! add_module {
-        () => {
-            let _body = cs.bodystack.pop().unwrap();
-        };
-    }
+fn gen_node(
+    backend: &mut Box<dyn Backend>,
+    cs: &mut CompileState,
+    node: &Node,
+) -> Result<(), Diagnostic> {
+    cs.locate(&node.token);
 
-    macro_rules! check_terminator {
-        ($expected:ident) => {
-            if cs
-                .defnstack
-                .pop()
-                .unwrap_or_else(|| cs.throw_error("no block to end"))
-                != DefinitionTypes::$expected
-            {
-                cs.throw_error(concat!(
-                    "Misplaced ",
-                    stringify!($expected),
-                    " block ending"
-                ));
-            }
-        };
-    }
+    match &node.kind {
+        NodeKind::Word(word) => gen_word(backend, cs, word)?,
 
-    match cs.word.clone().as_ref() {
-        // needs higher priority than fun & act keywords
-        x if *cs.defnstack.last().unwrap_or(&DefinitionTypes::Negative)
-            == DefinitionTypes::Linkin
-            && cs.metastack.last().unwrap().len() < 2 =>
-        {
-            cs.metastack.last_mut().unwrap().push(x.into());
-            // if we have all the arguments we needed
-            if cs.metastack.last_mut().unwrap().len() == 2 {
-                let mut meta = cs.metastack.pop().unwrap();
-                cs.defnstack.pop(); // end our defintion
-
-                let class = meta.remove(0);
-                let mname = meta.remove(0);
-
-                cs.methods.insert(
-                    mname.clone(),
-                    Method {
-                        name: mname.clone(),
-                        code: "LINKED IN".to_string(),
-                        class: match class.as_ref() {
-                            "fun" => MethodClass::Function,
-                            "act" => MethodClass::Action,
-                            other => cs.throw_error(&format!("Invalid link-in class {other}")),
-                        },
-                        // TODO: explicit typedef for linked in methods
-                        itypes: vec![],
-                        etypes: vec![],
-                    },
-                );
-
-                cs.add2body(&backend.linkin_function(&mname));
-            }
+        NodeKind::Literal(Literal::Integer(num)) => {
+            cs.add2body(&backend.push_integer(num));
+            cs.push_type(ValueTypes::Number);
         }
 
-        f if *cs.defnstack.last().unwrap_or(&DefinitionTypes::Negative)
-            == DefinitionTypes::Include =>
-        {
-            cs.defnstack.pop();
-            let mut pat = String::from(f);
-            pat.push_str(".dry");
-            let included_content =
-                String::from_utf8(fs::read(&pat).expect("Could not locate include")).unwrap();
-
-            // Save current file context
-            cs.file_stack
-                .push((cs.current_file.clone(), cs.linenumber - 1));
-            cs.current_file = pat.clone();
-            cs.linenumber = 1; // Start at line 1 for the included file
-            cs.tokenumber = 0;
-
-            // Track length of included content so we know when it's done
-            cs.prepend_remaining = included_content.len();
-            cs.prepend.push_str(&included_content);
-        }
-
-        v if *cs.defnstack.last().unwrap_or(&DefinitionTypes::Negative)
-            == DefinitionTypes::Variable =>
-        {
-            cs.defnstack.pop();
-            let vname = v;
+        NodeKind::Literal(Literal::Text(s)) => {
+            cs.add2body(&backend.push_string(s));
+            cs.push_type(ValueTypes::Text);
+        }
 
+        NodeKind::Literal(Literal::Binary(b)) => {
+            cs.add2body(if *b {
+                backend.push_true()
+            } else {
+                backend.push_false()
+            });
+            cs.push_type(ValueTypes::Binary)
+        }
+
+        NodeKind::VarDef(vname) => {
             if cs.methods.contains_key(vname) || cs.variable_in_scope(vname).is_some() {
-                println!("{:?}", cs.varscopes);
-                cs.throw_error(&format!(
+                return Err(cs.error(&format!(
                     "cant define variable, symbol {vname} is already taken"
-                ))
+                )));
             }
 
-            let vtype = cs.pop_type();
+            let vtype = cs.pop_type()?;
+            cs.moved.remove(vname);
 
             cs.varscopes
                 .last_mut()
                 .unwrap()
                 .insert(vname.to_string(), vtype);
-            cs.add2body(&backend.create_variable(vname));
+            if cs.varscopes.len() == 1 {
+                cs.add2body(&backend.create_global(vname));
+            } else {
+                cs.add2body(&backend.create_variable(vname));
+            }
         }
 
-        "fun:" | "fun" => {
-            new_definition!(Function);
-            cs.grow_varscopes();
-            cs.grow_typestack();
-            cs.grow_voidstack();
+        NodeKind::VarRead(vname) => {
+            if let Some(t) = cs.variable_in_scope(vname) {
+                // reading pushes a copy, so a linear variable can only be read once
+                if cs.is_linear(&t) {
+                    if cs.variable_scope(vname) < cs.loop_scopes.last().copied() {
+                        cs.type_error(&format!(
+                            "Linear variable {vname} can not be read inside a cycle, it may run more than once"
+                        ))?;
+                    } else if !cs.moved.insert(vname.to_string()) {
+                        cs.type_error(&format!("Linear variable {vname} was already used"))?;
+                    }
+                }
+                cs.push_type(t);
+                if cs.variable_scope(vname) == Some(0) {
+                    cs.add2body(&backend.read_global(vname));
+                } else {
+                    cs.add2body(&backend.read_variable(vname));
+                }
+            } else {
+                return Err(cs.error(&format!("Variable '{vname}' not in scope")));
+            }
         }
 
-        ":fun" => {
-            check_terminator!(Function);
-            add_method!(Function);
+        NodeKind::VarWrite(vname) => {
+            if let Some(t) = cs.variable_in_scope(vname) {
+                // a function only gets to change what it defined itself
+                let outside = cs.variable_scope(vname) < Some(cs.method_scope);
+                if outside && cs.defnstack.contains(&DefinitionTypes::Function) {
+                    return Err(cs.error(&format!(
+                        "Can not write to variable {vname} from inside a function, it outlives the call"
+                    )));
+                }
+                if cs.is_linear(&t) && !cs.moved.remove(vname) {
+                    cs.type_error(&format!(
+                        "Writing to {vname} would drop the linear value it still holds"
+                    ))?;
+                }
+                if cs.variable_scope(vname) == Some(0) {
+                    cs.add2body(&backend.write_global(vname));
+                } else {
+                    cs.add2body(&backend.write_variable(vname));
+                }
+                cs.expect_types(&[t])?
+            } else {
+                return Err(cs.error(&format!("Invalid write to variable {vname}, not found")));
+            }
         }
 
-        "act:" | "act" => {
-            new_definition!(Action);
+        NodeKind::Block(Block::Then(body)) => {
+            cs.expect_types(&[ValueTypes::Binary])?;
+            cs.defnstack.push(DefinitionTypes::Then);
+            cs.grow_bodystack();
             cs.grow_varscopes();
             cs.grow_typestack();
             cs.grow_voidstack();
-        }
+            let (before, diverged) = (cs.moved.clone(), cs.diverged);
 
-        ":act" => {
-            check_terminator!(Action);
-            add_method!(Action);
-        }
+            gen_nodes(backend, cs, &body.nodes)?;
+            cs.locate(&body.end);
+            cs.defnstack.pop();
 
-        // this keyword is funamentally unsafe, consider adding changing to unsafe_linkin or something like that
-        // or put it into an unsafe: block
-        "linkin" => {
-            cs.defnstack.push(DefinitionTypes::Linkin);
-            cs.grow_metastack();
-        }
+            let body = cs.bodystack.pop().unwrap();
+            cs.close_varscope()?;
 
-        "include" | "include:" => {
-            cs.defnstack.push(DefinitionTypes::Include);
-        }
+            cs.expect_no_type_footprint()?;
 
-        "then" | "then:" => {
-            cs.expect_types(&[ValueTypes::Binary]);
-            cs.defnstack.push(DefinitionTypes::Then);
-            cs.grow_bodystack();
-            cs.grow_varscopes();
-            cs.grow_typestack();
-            cs.grow_voidstack();
-        }
+            let inelect = cs.defnstack.last() == Some(&DefinitionTypes::Elect);
+            // in an elect the then leaves the whole block, otherwise we carry on as if it never ran
+            // a then that ends in break or return does not come back at all
+            match (cs.diverged, inelect) {
+                (true, _) => {}
+                (false, true) => cs.elect_exits.last_mut().unwrap().push(cs.moved.clone()),
+                (false, false) => cs.expect_same_moved(&before)?,
+            }
+            cs.moved = before;
+            cs.diverged = diverged;
 
-        ":then" => {
-            check_terminator!(Then);
-            add_then_block!();
+            cs.add2body(&backend.create_conditional_statement(body, inelect));
         }
 
-        "elect" | "elect:" | "when" | "when:" => {
+        NodeKind::Block(Block::When(body)) => {
             cs.defnstack.push(DefinitionTypes::Elect);
             cs.grow_bodystack();
-        }
+            cs.elect_exits.push(vec![]);
+
+            gen_nodes(backend, cs, &body.nodes)?;
+            cs.locate(&body.end);
+            cs.defnstack.pop();
 
-        ":elect" | ":when" => {
-            add_elect_block!();
+            // falling through the end is one more way out, unless it breaks or returns
+            let mut exits = cs.elect_exits.pop().unwrap();
+            if cs.diverged && !exits.is_empty() {
+                cs.moved = exits.remove(0);
+                cs.diverged = false;
+            }
+            for exit in exits {
+                cs.expect_same_moved(&exit)?;
+            }
+
+            let body = cs.bodystack.pop().unwrap();
+            cs.add2body(&backend.create_elect_block(body));
         }
 
-        "loop" | "loop:" | "cycle" | "cycle:" => {
+        NodeKind::Block(Block::Cycle(body)) => {
             cs.defnstack.push(DefinitionTypes::Loop);
             cs.grow_bodystack();
+            cs.grow_varscopes();
             cs.grow_typestack();
             cs.grow_voidstack();
-        }
-
-        ":loop" | ":cycle" => {
-            check_terminator!(Loop);
-            add_loop_block!();
-        }
-
-        "break" => {
-            cs.add2body(&backend.loop_break());
-        }
+            cs.loop_scopes.push(cs.varscopes.len() - 1);
+            let diverged = cs.diverged;
 
-        "return" => {
-            cs.add2body(&backend.method_return());
-        }
+            gen_nodes(backend, cs, &body.nodes)?;
+            cs.locate(&body.end);
+            cs.defnstack.pop();
 
-        "var" | "var:" => {
-            cs.defnstack.push(DefinitionTypes::Variable);
-        }
+            let body = cs.bodystack.pop().unwrap();
+            cs.loop_scopes.pop();
+            cs.close_varscope()?;
+            // the way out of a cycle is break, which already checked what it leaves behind
+            cs.diverged = diverged;
 
-        "module" => {
-            cs.defnstack.push(DefinitionTypes::Module);
-            cs.grow_bodystack();
-            cs.grow_metastack();
-        }
+            cs.expect_no_type_footprint()?;
 
-        ":module" => {
-            check_terminator!(Module);
-            add_module!();
+            cs.add2body(&backend.create_loop_block(body));
         }
 
-        "struct" => {}
-
-        ";" | "end" => {
-            match cs
-                .defnstack
-                .pop()
-                .unwrap_or_else(|| cs.throw_error(" - Misplaced ;"))
-            {
-                // keep {} notation instead of , for the macros to work
-                DefinitionTypes::Function => {
-                    add_method!(Function);
-                }
-                DefinitionTypes::Action => {
-                    add_method!(Action);
-                }
-                DefinitionTypes::Then => {
-                    add_then_block!();
-                }
-                DefinitionTypes::Loop => {
-                    add_loop_block!();
-                }
-                DefinitionTypes::Elect => {
-                    add_elect_block!();
-                }
-                DefinitionTypes::Module => {
-                    add_module!();
-                }
-                _ => todo!(),
-            }
+        NodeKind::Break => {
+            let Some(&scope) = cs.loop_scopes.last() else {
+                return Err(cs.error("Can not break outside of a cycle"));
+            };
+            cs.leave_scopes(scope, "break")?;
+            cs.add2body(&backend.loop_break());
         }
 
-        _mac if (false) => {} // future for macro expansion
-
-        // TODO: optimize this into an argcount stack, which decrements top on each metastack push
-        // TODO ALT basically all of these just grab x args, but maybe in the future they will also perform immediate work with them, so who knows actually?
-        // TODO ALT just break this into a bunch of macro for ease of use & reading
-        fname
-            if *cs.defnstack.last().unwrap_or(&DefinitionTypes::Negative)
-                == DefinitionTypes::Function
-                && cs.metastack.last().unwrap().is_empty() =>
-        {
-            cs.metastack.last_mut().unwrap().push(fname.into())
+        NodeKind::Return => {
+            cs.leave_scopes(cs.method_scope, "return")?;
+            cs.add2body(&backend.method_return());
         }
+    }
 
-        aname
-            if *cs.defnstack.last().unwrap_or(&DefinitionTypes::Negative)
-                == DefinitionTypes::Action
-                && cs.metastack.last().unwrap().is_empty() =>
-        {
-            cs.metastack.last_mut().unwrap().push(aname.into())
-        }
+    Ok(())
+}
 
-        // body code must start here
+// builtins and calls to user defined methods
+fn gen_word(
+    backend: &mut Box<dyn Backend>,
+    cs: &mut CompileState,
+    word: &str,
+) -> Result<(), Diagnostic> {
+    macro_rules! add_builtin {
+        ($prop:ident) => {{
+            cs.add2body(backend.$prop())
+        }};
+    }
 
+    match word {
         // if let Some(_) is experimental here, so double chcking is required
         metname if cs.methods.contains_key(metname) => {
             let met = cs.methods.get(metname).unwrap();
-            if met.class == MethodClass::Action {
-                cs.before_action();
+            if !met.pure {
+                cs.before_action()?;
             }
-            let it = met.itypes.clone();
-            let et = met.etypes.clone();
-            cs.expect_types(&it);
+            let (it, et) = (met.itypes.clone(), met.etypes.clone());
+            let (it, et) = cs.instantiate(&it, &et);
+            cs.expect_types(&it)?;
             cs.add2body(&backend.user_function(metname));
             cs.push_types(&et);
         }
 
-        var if var.starts_with('$') => {
-            let vname = var.strip_prefix('$').unwrap();
-            if let Some(t) = cs.variable_in_scope(vname) {
-                cs.push_type(t);
-                cs.add2body(&backend.read_variable(vname));
-            } else {
-                cs.throw_error(&format!("Variable '{vname}' not in scope"))
-            }
-        }
-
-        num if regexint.is_match(num) => {
-            cs.add2body(&backend.push_integer(num));
-            cs.push_type(ValueTypes::Number);
-        }
-
-        setvar if setvar.ends_with('!') => {
-            let vname = setvar.strip_suffix('!').unwrap();
-            if let Some(t) = cs.variable_in_scope(vname) {
-                cs.add2body(&backend.write_variable(vname));
-                cs.expect_types(&[t])
-            } else {
-                cs.throw_error(&format!("Invalid write to variable {vname}, not found"))
-            }
-        }
-
-        "true" => {
-            add_builtin!(push_true);
-            cs.push_type(ValueTypes::Binary)
-        }
-
-        "false" => {
-            add_builtin!(push_false);
-            cs.push_type(ValueTypes::Binary)
-        }
         "+" => {
-            cs.expect_types(&[ValueTypes::Number, ValueTypes::Number]);
+            cs.expect_types(&[ValueTypes::Number, ValueTypes::Number])?;
             add_builtin!(fun_add);
             cs.push_type(ValueTypes::Number);
         }
         "-" => {
-            cs.expect_types(&[ValueTypes::Number, ValueTypes::Number]);
+            cs.expect_types(&[ValueTypes::Number, ValueTypes::Number])?;
             add_builtin!(fun_sub);
             cs.push_type(ValueTypes::Number);
         }
         "*" => {
-            cs.expect_types(&[ValueTypes::Number, ValueTypes::Number]);
+            cs.expect_types(&[ValueTypes::Number, ValueTypes::Number])?;
             add_builtin!(fun_mul);
             cs.push_type(ValueTypes::Number);
         }
         "/" => {
-            cs.expect_types(&[ValueTypes::Number, ValueTypes::Number]);
+            cs.expect_types(&[ValueTypes::Number, ValueTypes::Number])?;
             add_builtin!(fun_div);
             cs.push_type(ValueTypes::Number);
         }
         "mod" => {
-            cs.expect_types(&[ValueTypes::Number, ValueTypes::Number]);
+            cs.expect_types(&[ValueTypes::Number, ValueTypes::Number])?;
             add_builtin!(fun_mod);
             cs.push_type(ValueTypes::Number);
         }
         "^" | "copy" => {
-            cs.expect_types(&[ValueTypes::Number]);
+            let t = cs.pop_type()?;
+            cs.expect_unrestricted(&t, "copy")?;
             add_builtin!(fun_copy);
-            cs.push_type(ValueTypes::Number);
-            cs.push_type(ValueTypes::Number);
+            cs.push_type(t.clone());
+            cs.push_type(t);
         }
         "v" | "drop" => {
-            let _t = cs.pop_type();
+            let t = cs.pop_type()?;
+            cs.expect_unrestricted(&t, "drop")?;
             add_builtin!(fun_drop);
         }
         "swap" => {
-            let t1 = cs.pop_type();
-            let t2 = cs.pop_type();
+            let t1 = cs.pop_type()?;
+            let t2 = cs.pop_type()?;
             add_builtin!(fun_swap);
             cs.push_type(t1);
             cs.push_type(t2);
         }
         "equals?" | "=?" => {
-            let _t2 = cs.pop_type();
-            let _t1 = cs.pop_type();
+            expect_comparable(cs, word)?;
             add_builtin!(fun_simple_equality);
             cs.push_type(ValueTypes::Binary);
         }
         "nequals?" | "!=?" => {
-            let _t2 = cs.pop_type();
-            let _t1 = cs.pop_type();
+            expect_comparable(cs, word)?;
             add_builtin!(fun_simple_non_equality);
             cs.push_type(ValueTypes::Binary);
         }
         "not" => {
-            cs.expect_types(&[ValueTypes::Binary]);
+            cs.expect_types(&[ValueTypes::Binary])?;
             add_builtin!(fun_logical_not);
             cs.push_type(ValueTypes::Binary);
         }
         "either?" => {
-            cs.expect_types(&[ValueTypes::Binary, ValueTypes::Binary]);
+            cs.expect_types(&[ValueTypes::Binary, ValueTypes::Binary])?;
             add_builtin!(fun_logical_or);
             cs.push_type(ValueTypes::Binary);
         }
         "both?" => {
-            cs.expect_types(&[ValueTypes::Binary, ValueTypes::Binary]);
+            cs.expect_types(&[ValueTypes::Binary, ValueTypes::Binary])?;
             add_builtin!(fun_logical_and);
             cs.push_type(ValueTypes::Binary);
         }
         "greater?" | ">?" => {
-            cs.expect_types(&[ValueTypes::Number, ValueTypes::Number]);
+            cs.expect_types(&[ValueTypes::Number, ValueTypes::Number])?;
             add_builtin!(fun_num_greater);
             cs.push_type(ValueTypes::Binary);
         }
         ">=?" => {
-            cs.expect_types(&[ValueTypes::Number, ValueTypes::Number]);
+            cs.expect_types(&[ValueTypes::Number, ValueTypes::Number])?;
             add_builtin!(fun_num_greater_or_equal);
             cs.push_type(ValueTypes::Binary);
         }
         "<?" => {
-            cs.expect_types(&[ValueTypes::Number, ValueTypes::Number]);
+            cs.expect_types(&[ValueTypes::Number, ValueTypes::Number])?;
             add_builtin!(fun_num_less_than);
             cs.push_type(ValueTypes::Binary);
         }
         "=<?" => {
-            cs.expect_types(&[ValueTypes::Number, ValueTypes::Number]);
+            cs.expect_types(&[ValueTypes::Number, ValueTypes::Number])?;
             add_builtin!(fun_num_less_than_or_equal);
             cs.push_type(ValueTypes::Binary);
         }
         "xor" => {
-            cs.expect_types(&[ValueTypes::Number, ValueTypes::Number]);
+            cs.expect_types(&[ValueTypes::Number, ValueTypes::Number])?;
             add_builtin!(fun_exclusive_or);
             cs.push_type(ValueTypes::Number);
         }
 
-        word => cs.throw_error(&format!("Unknown token '{}'", word)),
+        word => return Err(cs.error(&format!("Unknown token '{}'", word))),
+    }
+
+    Ok(())
+}
+
+// equality works on two values of the same type, compared by what is on the stack
+fn expect_comparable(cs: &mut CompileState, word: &str) -> Result<(), Diagnostic> {
+    let t2 = cs.pop_type()?;
+    let t1 = cs.pop_type()?;
+    if !cs.unify(&t1, &t2) {
+        let (t1, t2) = (cs.resolve(&t1), cs.resolve(&t2));
+        return cs.type_error(&format!("Can not compare {t1} with {t2} using {word}"));
+    }
+    cs.expect_unrestricted(&t1, "compare")?;
+    if cs.resolve(&t1) == ValueTypes::Text {
+        return cs.type_error(&format!(
+            "Can not compare Text using {word}, it would only compare where the strings are stored"
+        ));
     }
+    Ok(())
 }
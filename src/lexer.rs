@@ -0,0 +1,182 @@
+/*
+* Copyright (C) 2025 Filip Chovanec
+*
+* This program is free software: you can redistribute it and/or modify
+* it under the terms of the GNU General Public License as published by
+* the Free Software Foundation, either version 3 of the License, or
+* (at your option) any later version.
+*
+* This program is distributed in the hope that it will be useful,
+* but WITHOUT ANY WARRANTY; without even the implied warranty of
+* MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+* GNU General Public License for more details.
+*
+* You should have received a copy of the GNU General Public License
+* along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use crate::diagnostic::{Diagnostic, Severity, Span};
+use regex::Regex;
+use std::sync::LazyLock;
+
+static INTEGER: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^-?\d+$").unwrap());
+
+// words that shape the program instead of pushing or calling something
+pub const KEYWORDS: &[&str] = &[
+    "fun", "fun:", ":fun", "act", "act:", ":act", "linkin", "include", "include:", "then", "then:",
+    ":then", "elect", "elect:", ":elect", "when", "when:", ":when", "loop", "loop:", ":loop",
+    "cycle", "cycle:", ":cycle", "break", "return", "var", "var:", "module", ":module", "struct",
+    ";", "end",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenKind {
+    Word,
+    Integer,
+    String,     // "...", text holds the content without quotes
+    Comment,    // # ... #
+    Annotation, // ( ... ), text holds the content without parentheses
+    Keyword,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub text: String,
+    pub span: Span,
+    pub word: isize, // index of the word on its line, 0 for strings, comments and annotations
+}
+
+pub fn is_integer(word: &str) -> bool {
+    INTEGER.is_match(word)
+}
+
+fn classify(word: &str) -> TokenKind {
+    if KEYWORDS.contains(&word) {
+        TokenKind::Keyword
+    } else if is_integer(word) {
+        TokenKind::Integer
+    } else {
+        TokenKind::Word
+    }
+}
+
+// single pass over the source, fails only on strings, comments or annotations left open
+pub fn lex(file: &str, src: &str) -> Result<Vec<Token>, Diagnostic> {
+    let mut tokens = vec![];
+
+    let mut line: isize = 1;
+    let mut column: isize = 0;
+    let mut words: isize = 0; // words seen on the current line
+
+    // the token being built, its kind is decided when it ends
+    let mut open: Option<(TokenKind, char)> = None; // kind and closing delimiter for delimited tokens
+    let mut text = String::new();
+    // line, column and byte where it starts and where its last character ends
+    // kept as plain numbers, the span is only built once the token is done
+    let mut start = (0, 0, 0);
+    let mut end = (0, 0, 0);
+
+    macro_rules! span {
+        () => {
+            Span {
+                file: file.to_string(),
+                start_line: start.0,
+                start_column: start.1,
+                end_line: end.0,
+                end_column: end.1,
+                start: start.2,
+                end: end.2,
+            }
+        };
+    }
+
+    macro_rules! finish {
+        ($kind:expr) => {{
+            let kind = $kind;
+            let word = match kind {
+                TokenKind::Word | TokenKind::Integer | TokenKind::Keyword => {
+                    words += 1;
+                    words
+                }
+                _ => 0,
+            };
+            tokens.push(Token {
+                kind,
+                text: std::mem::take(&mut text),
+                span: span!(),
+                word,
+            });
+        }};
+    }
+
+    for (byte, c) in src.char_indices() {
+        column += 1;
+        let here = (line, column, byte);
+        let after = (line, column, byte + c.len_utf8());
+
+        match open {
+            Some((kind, close)) => {
+                if c == close {
+                    open = None;
+                    end = after;
+                    finish!(kind);
+                } else {
+                    text.push(c);
+                }
+            }
+            None => {
+                let delimited = match c {
+                    '#' => Some((TokenKind::Comment, '#')),
+                    '"' => Some((TokenKind::String, '"')),
+                    '(' => Some((TokenKind::Annotation, ')')),
+                    _ => None,
+                };
+
+                if matches!(c, ' ' | '\n' | '\t' | '\r') || delimited.is_some() {
+                    if !text.is_empty() {
+                        finish!(classify(&text));
+                    }
+                    if delimited.is_some() {
+                        open = delimited;
+                        start = here;
+                        end = after;
+                    }
+                } else {
+                    if text.is_empty() {
+                        start = here;
+                    }
+                    end = after;
+                    text.push(c);
+                }
+            }
+        }
+
+        if c == '\n' {
+            line += 1;
+            column = 0;
+            words = 0;
+        }
+    }
+
+    if let Some((kind, close)) = open {
+        let what = match kind {
+            TokenKind::Comment => "comment",
+            TokenKind::String => "string",
+            _ => "annotation",
+        };
+        return Err(Diagnostic::new(
+            Severity::Error,
+            span!(),
+            0,
+            &format!("Unterminated {what}, expected a closing {close}"),
+            src,
+        ));
+    }
+
+    if !text.is_empty() {
+        finish!(classify(&text));
+    }
+
+    Ok(tokens)
+}
@@ -15,225 +15,599 @@
 * along with this program.  If not, see <https://www.gnu.org/licenses/>.
 */
 
-use clap::Parser;
+// diagnostics are only ever created on the error path, so their size does not matter
+#![allow(clippy::result_large_err)]
+
+use clap::error::ErrorKind;
+use clap::{CommandFactory, Parser, Subcommand};
+use rustyline::error::ReadlineError;
+use rustyline::DefaultEditor;
 use std::env;
 use std::fs;
 use std::io;
 use std::io::Write;
 use std::path::{Path, PathBuf};
-use std::process::Command;
+use std::process::{exit, Command, ExitStatus, Stdio};
 
-use serde::Deserialize;
+use manifest::Manifest;
+use state::{CompileOptions, TypeCheck};
+use strum_macros::IntoStaticStr;
+use targets::{quote, BuildPaths, TargetDesc, TargetSpec, Targets};
 
+pub mod ast;
 pub mod backends;
+pub mod diagnostic;
 pub mod frontend;
+pub mod lexer;
+pub mod manifest;
+pub mod parser;
+pub mod repl;
 pub mod state;
+pub mod targets;
 #[cfg(test)]
 pub mod test;
+pub mod vm;
+
+// keeps arrow key history between sessions
+fn history_file() -> Option<PathBuf> {
+    env::home_dir().map(|home| home.join(".dryft_history"))
+}
+
+fn repl(options: CompileOptions) {
+    let mut session = repl::Session::new(options);
+    prelude(&mut session);
 
-fn repl(targetspec: TargetSpec) {
-    // temporary disable because of how the repl pastes code into main directly
-    if targetspec.backend == "x86" {
-        println!("Warning: the x86 backend is not stable in the REPL environment");
+    let mut editor = DefaultEditor::new().expect("Failed to open the terminal");
+    let history = history_file();
+    if let Some(h) = &history {
+        let _ = editor.load_history(h); // there is none on the first run
     }
 
+    let mut input = String::new();
     loop {
-        print!("Dryft repl> ");
-        io::stdout().flush().unwrap(); // flush so it appears immediately
-        let mut input = String::new();
-        io::stdin()
-            .read_line(&mut input)
-            .expect("Failed to read line");
+        // a definition or string spanning lines keeps reading until it is closed
+        let prompt = if input.is_empty() {
+            "Dryft repl> "
+        } else {
+            "       ...> "
+        };
+        match editor.readline(prompt) {
+            Ok(line) => {
+                input.push_str(&line);
+                input.push('\n');
+            }
+            Err(ReadlineError::Interrupted) => {
+                input.clear(); // ctrl-c drops what was typed so far
+                continue;
+            }
+            Err(ReadlineError::Eof) => break, // end of input
+            Err(e) => panic!("Failed to read line: {e}"),
+        }
+        if parser::unfinished(&input) {
+            continue;
+        }
+        let input = std::mem::take(&mut input);
         if input.trim().is_empty() {
             continue;
         }
+        let _ = editor.add_history_entry(input.trim());
 
-        match input.trim() {
+        let (command, argument) = input
+            .trim()
+            .split_once(char::is_whitespace)
+            .map(|(c, a)| (c, a.trim()))
+            .unwrap_or((input.trim(), ""));
+        match command {
             ".help" | "help" => {
+                println!("definitions are kept and code runs right away, on the stack left by earlier lines");
                 println!(".help/help  => display this screen");
                 println!(".exit/.quit => leave the REPL, terminating this process");
-                println!(".view       => inspect the contents of generated IR from last execution")
+                println!(".view       => inspect the bytecode compiled so far");
+                println!(".stack      => show the values on the stack and their types");
+                println!(".words      => list every known word and its stack effect");
+                println!(".see <word> => show the source of a word and the code it compiled to");
+                println!(".load <file> => include a file, keeping its definitions");
+                println!(".reset      => forget everything and start over")
             }
-            ".view" => {
-                let ir = &String::from_utf8(fs::read(&targetspec.intermediate).unwrap()).unwrap();
-                println!("{}", ir);
+            ".view" => println!("{}", session.bytecode),
+            ".stack" => print!("{}", session.describe_stack()),
+            ".words" => print!("{}", session.describe_words()),
+            ".see" => match session.describe_word(argument) {
+                Some(text) => print!("{text}"),
+                None => eprintln!("Unknown word {argument}"),
+            },
+            ".load" if argument.is_empty() => eprintln!("Expected a file to load"),
+            ".load" => {
+                let result = session.load(argument, &mut io::stdout());
+                show(result);
+            }
+            ".reset" => {
+                session.reset();
+                prelude(&mut session);
             }
             ".exit" | ".quit" => break,
             _ => {
-                let src = format!("include std/io act: main {} ;", input);
-                let backend = crate::backends::select(&targetspec.backend);
-                fs::write(
-                    &targetspec.intermediate,
-                    frontend::compile_full(backend, &src),
-                )
-                .unwrap();
-                stdlib(targetspec.stdlib.as_ref().unwrap_or(&"".to_string()));
-                assemble(targetspec.assemble.as_ref().unwrap_or(&"".to_string()));
-                link(targetspec.link.as_ref().unwrap_or(&"".to_string()));
-                interpret(
-                    targetspec
-                        .interpret
-                        .clone()
-                        .unwrap_or("./a.out".to_string())
-                        .as_ref(),
-                );
+                let result = session.eval(&input, &mut io::stdout());
+                show(result);
             }
         }
     }
+
+    if let Some(h) = &history {
+        if let Err(e) = editor.save_history(h) {
+            eprintln!("Could not save history to {}: {e}", h.display());
+        }
+    }
 }
 
-fn build_file(inp: &Path, out: &Path, backend_name: &str) {
-    let src = &String::from_utf8(fs::read(inp).unwrap_or("".into())).unwrap();
-    if src.is_empty() {
-        println!("Nothing to compile :/");
-    } else {
-        let backend = crate::backends::select(backend_name);
-        fs::write(out, frontend::compile_full(backend, src)).unwrap();
+// every session starts with the basic io words
+fn prelude(session: &mut repl::Session) {
+    if let Err(e) = session.eval("include std/io", &mut io::stdout()) {
+        report(e);
     }
 }
 
-// output the text or nothing if its n empty string
-fn simple_print(x: &str) {
-    print!("{}", x);
-    io::stdout().flush().unwrap();
+fn show(result: Result<Vec<diagnostic::Diagnostic>, repl::ReplError>) {
+    match result {
+        Ok(warnings) => {
+            for d in warnings.iter() {
+                eprint!("{}", d.render());
+            }
+            io::stdout().flush().unwrap();
+        }
+        Err(e) => report(e),
+    }
 }
 
-// compile the standard library
-fn stdlib(cmd: &str) {
-    let (_, stderr) = bash(cmd);
-    simple_print(&stderr);
+fn report(e: repl::ReplError) {
+    match e {
+        repl::ReplError::Compile(diagnostics) => {
+            for d in diagnostics.iter() {
+                eprint!("{}", d.render());
+            }
+        }
+        repl::ReplError::Runtime(e) => eprintln!("{e}"),
+    }
 }
 
-// pass IR generated by dryftc into an external compiler, like gcc or llvm
-fn assemble(cmd: &str) {
-    let (_, stderr) = bash(cmd);
-    simple_print(&stderr);
+// prints every diagnostic, returns the completed IR only if there were no errors
+fn compile_source(
+    mut backend: Box<dyn backends::Backend>,
+    file: &str,
+    src: &str,
+    options: &CompileOptions,
+) -> Option<String> {
+    match frontend::compile_file(&mut backend, file, src, options.clone()) {
+        Ok(cs) => {
+            for d in cs.diagnostics.iter() {
+                eprint!("{}", d.render());
+            }
+            Some(backend.complete(&cs.out.expect("No code compiled :(")))
+        }
+        Err(diagnostics) => {
+            for d in diagnostics.iter() {
+                eprint!("{}", d.render());
+            }
+            None
+        }
+    }
 }
 
-// link objects into final executable
-fn link(cmd: &str) {
-    let (_, stderr) = bash(cmd);
-    simple_print(&stderr);
+// returns false if compilation failed
+fn build_file(inp: &Path, out: &Path, backend_name: &str, options: &CompileOptions) -> bool {
+    let src = &fs::read_to_string(inp).unwrap_or_else(|e| {
+        fail(
+            Stage::Compile,
+            &format!("could not read {}: {e}", inp.display()),
+        )
+    });
+    // carrying on would assemble and run whatever an earlier build left behind
+    if src.trim().is_empty() {
+        fail(
+            Stage::Compile,
+            &format!("{} is empty, nothing to compile", inp.display()),
+        );
+    }
+    let backend = crate::backends::select(backend_name);
+    match compile_source(backend, &inp.display().to_string(), src, options) {
+        Some(ir) => {
+            fs::write(out, ir).unwrap();
+            true
+        }
+        None => false,
+    }
+}
+
+// the steps of building a program, a failed step makes dryftc exit with its number
+// 1 is left for bad arguments and vm errors and 2 for clap, so every step can be told apart
+#[derive(Debug, Clone, Copy, IntoStaticStr)]
+#[strum(serialize_all = "lowercase")]
+enum Stage {
+    Compile = 10,
+    Stdlib = 11,
+    Assemble = 12,
+    Link = 13,
+    Native = 14,
+    Preflight = 15,
+}
+
+// stops dryftc, naming the step that went wrong
+fn fail(stage: Stage, msg: &str) -> ! {
+    let name: &'static str = stage.into();
+    eprintln!("error: {name} step failed: {msg}");
+    exit(stage as i32);
+}
+
+// run an external step of the pipeline: stdlib compiles the standard library,
+// native compiles the C sources of a project, assemble passes the IR generated by dryftc
+// to an external compiler like gcc or llvm, link turns the objects into the final executable
+fn step(stage: Stage, cmd: Option<&str>) {
+    if let Err(msg) = run_step(cmd.unwrap_or("")) {
+        fail(stage, &msg);
+    }
+}
+
+// what went wrong if cmd failed, its stderr is passed on either way
+fn run_step(cmd: &str) -> Result<(), String> {
+    match bash(cmd) {
+        // warnings of the tools stay on stderr, away from the output of the program
+        Ok((status, stderr)) if status.success() => {
+            eprint!("{stderr}");
+            Ok(())
+        }
+        Ok((status, stderr)) => {
+            eprint!("{stderr}");
+            Err(format!("`{cmd}` exited with code {}", exit_code(status)))
+        }
+        Err(e) => Err(format!("could not run `{cmd}`: {e}")),
+    }
 }
 
 // execute step after externalize(), necessary for VM-based backends
-// TODO: currently waits for finish to disply stdout, which is not good for interactive apps. Start a proper process in future.
-fn interpret(cmd: &str) {
-    let (stdout, _) = bash(cmd);
-    println!("{}", stdout);
+// the program gets our terminal, so output shows up as it is written, returns its exit code
+fn interpret(cmd: &str) -> i32 {
+    let status = Command::new("bash")
+        .arg("-c")
+        .arg(cmd)
+        .status()
+        .expect("Failed to execute bash");
+    exit_code(status)
+}
+
+// like a shell would report it, a program killed by a signal exits with 128 + the signal
+fn exit_code(status: ExitStatus) -> i32 {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        if let Some(signal) = status.signal() {
+            return 128 + signal;
+        }
+    }
+    status.code().unwrap_or(1)
+}
+
+// run what was just built, the vm backend runs inside dryftc
+fn execute(targetspec: &TargetSpec, paths: &BuildPaths) -> i32 {
+    if targetspec.backend == "vm" {
+        let bytecode = fs::read_to_string(&paths.intermediate).unwrap_or_default();
+        let result = vm::run(&bytecode, &mut io::stdout());
+        io::stdout().flush().unwrap();
+        return match result {
+            Ok(()) => 0,
+            Err(e) => {
+                eprintln!("{e}");
+                1
+            }
+        };
+    }
+    interpret(&paths.expand(targetspec.interpret.as_deref().unwrap_or("{output}")))
 }
 
-// returns stdout and stderr post cmd execution
-fn bash(cmd: &str) -> (String, String) {
+// returns the status of cmd and its stderr, stdout is passed through
+fn bash(cmd: &str) -> io::Result<(ExitStatus, String)> {
+    // targets leave out the steps they do not need
+    if cmd.trim().is_empty() {
+        return Ok((ExitStatus::default(), String::new()));
+    }
     let output = Command::new("bash")
         .arg("-c")
         .arg(cmd)
-        .output()
-        .expect("Failed to execute bash");
-    (
-        String::from_utf8_lossy(&output.stdout).to_string(),
+        .stdin(Stdio::null())
+        .stdout(Stdio::inherit())
+        .output()?;
+    Ok((
+        output.status,
         String::from_utf8_lossy(&output.stderr).to_string(),
-    )
+    ))
 }
 
-#[derive(Deserialize)]
-struct TargetDesc {
-    // only provides linux support for now, windows/mac support in the future
-    unix: Option<TargetSpec>,
-    windows: Option<TargetSpec>,
+// where includes are looked for when they are not next to the file including them
+fn include_paths(flags: Vec<PathBuf>, manifest: Option<&Manifest>) -> Vec<PathBuf> {
+    let mut paths = flags;
+    if let Some(dryft_path) = env::var_os("DRYFT_PATH") {
+        paths.extend(env::split_paths(&dryft_path));
+    }
+    paths.extend(manifest.map(|m| m.include_paths()).unwrap_or_default());
+    paths
 }
 
-// platform independent
-#[derive(Deserialize)]
-struct TargetSpec {
-    //dependencies: Vec<String>,
-    backend: String,
-    intermediate: PathBuf,    // file to write dryftc output to
-    assemble: Option<String>, // command describing how to use an external compiler to finalize compilation.
-    link: Option<String>,
-    interpret: Option<String>, // command to run the final product. If none, use default system execute function (TODO)
-    stdlib: Option<String>,
+// one line per target: name, backend, required tools and where it was defined
+fn list_targets(targets: &Targets) {
+    for (name, origin, desc) in targets.list() {
+        let about = match desc.as_ref().map(|d| d.host()) {
+            Ok(Some(spec)) => {
+                let tools = if spec.dependencies.is_empty() {
+                    "-".to_string()
+                } else {
+                    spec.dependencies.join(", ")
+                };
+                format!("{:<8} {tools:<20}", spec.backend)
+            }
+            Ok(None) => format!("{:<29}", "not available on this platform"),
+            Err(e) => format!("invalid: {e}"),
+        };
+        println!("{name:<10} {about} ({origin})");
+    }
 }
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
 pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Project>,
+
     pub inputfile: Option<PathBuf>,
 
-    #[arg(short = 't', long = "target")]
-    #[arg(default_value = "gcc")]
-    /// One of the targets defined in src/targets/
-    pub target: String,
+    #[arg(short = 't', long = "target", global = true)]
+    /// One of the targets listed by --list-targets, gcc unless the project says otherwise
+    pub target: Option<String>,
 
     /// only outputs the final dryftc assembly, no external tooling will be called
-    #[arg(long = "assembly-only")]
+    #[arg(long = "assembly-only", global = true)]
     pub assembly_only: bool,
 
     /// only outputs an object file, without additional library linking
-    #[arg(long = "object-only")]
+    #[arg(long = "object-only", global = true)]
     pub object_only: bool,
 
     /// output file where the dryftc assembly will be stored
-    #[arg(short = 'a', long = "assembly-out")]
+    #[arg(short = 'a', long = "assembly-out", global = true)]
     pub assembly_out: Option<PathBuf>,
 
+    /// directory to search for includes, before DRYFT_PATH and the project's include paths
+    #[arg(short = 'I', long = "include", global = true)]
+    pub include: Vec<PathBuf>,
+
+    /// where to put the executable, a.out unless the target or project says otherwise
+    #[arg(short = 'o', long = "output", global = true)]
+    pub output: Option<PathBuf>,
+
+    /// directory for intermediate files, build/ or build/<output name> when -o is given
+    #[arg(long = "build-dir", global = true)]
+    pub build_dir: Option<PathBuf>,
+
     /// provide a path to custom target.toml descriptor
-    #[arg(long = "custom-target")]
+    #[arg(long = "custom-target", global = true)]
     pub custom_target: Option<String>, // should be pathbuf but oh well
 
+    /// show every known target, its backend and the tools it needs
+    #[arg(long = "list-targets", global = true)]
+    pub list_targets: bool,
+
     /// Run the final executable using the pre-defined interpreter
     #[arg(short = 'r', long = "run")]
     pub is_run: bool,
+
+    /// How strictly to enforce types, strict by default when built with the typesystem feature
+    #[arg(long = "typecheck", value_enum, global = true)]
+    pub typecheck: Option<TypeCheck>,
+}
+
+// work on the project described by the closest dryft.toml, looking from dir up
+#[derive(Subcommand, Debug, Clone, PartialEq)]
+pub enum Project {
+    /// Build the project
+    Build { dir: Option<PathBuf> },
+    /// Build the project and run it
+    Run { dir: Option<PathBuf> },
+    /// Remove everything the build produced
+    Clean { dir: Option<PathBuf> },
+}
+
+impl Project {
+    fn dir(&self) -> PathBuf {
+        let (Project::Build { dir } | Project::Run { dir } | Project::Clean { dir }) = self;
+        let cwd = env::current_dir().expect("No working directory");
+        dir.as_ref().map(|d| cwd.join(d)).unwrap_or(cwd)
+    }
+}
+
+// remove what a build produced and nothing else, the build directory may be shared
+fn clean(paths: &BuildPaths, native: &[PathBuf]) {
+    let objects = native
+        .iter()
+        .enumerate()
+        .map(|(n, src)| paths.native_object(n, src));
+    for file in [
+        &paths.intermediate,
+        &paths.object,
+        &paths.stdlib_object,
+        &paths.output,
+    ]
+    .into_iter()
+    .cloned()
+    .chain(objects)
+    .chain(paths.stdlib_files())
+    {
+        let _ = fs::remove_file(file);
+    }
+    // the stdlib directories go too, unless something else was put in them
+    for file in paths.stdlib_files() {
+        for dir in file.ancestors().skip(1) {
+            if !dir.starts_with(&paths.stdlib_dir) || fs::remove_dir(dir).is_err() {
+                break;
+            }
+        }
+    }
 }
 
 fn main() {
     let cli = Cli::parse();
+    // flags may go before or after the subcommand, but the project already names its entry
+    if let (Some(_), Some(f)) = (&cli.command, &cli.inputfile) {
+        Cli::command()
+            .error(
+                ErrorKind::ArgumentConflict,
+                format!(
+                    "{} can not be combined with build, run or clean, the project names its entry",
+                    f.display()
+                ),
+            )
+            .exit();
+    }
 
-    let target_name = cli.target;
-    let target_raw = String::from_utf8(
-        fs::read(
-            cli.custom_target
-                .unwrap_or(format!("src/targets/{target_name}.toml")),
-        )
-        .expect("Unknown target"),
-    )
-    .expect("Bad utf8 in target description");
-    let target_toml: TargetDesc = toml::from_str(&target_raw).expect("Invalid target description");
-
-    // TODO: add external dependency checks
-
-    let mut targetspec = match env::consts::FAMILY {
-        "unix" => target_toml
-            .unix
-            .expect("Your platform (UNIX) does not yet support this target"),
-        "windows" => target_toml
-            .windows
-            .expect("Your platform (WINDOWS) does not yet support this target"),
-        other => panic!("Unknown host OS family {other}"),
-    };
+    let manifest = cli.command.as_ref().map(|project| {
+        Manifest::find(&project.dir()).unwrap_or_else(|e| {
+            eprintln!("error: {e}");
+            exit(1);
+        })
+    });
+
+    let targets = Targets::discover(manifest.as_ref().map(|m| m.root.as_path()));
+    if cli.list_targets {
+        list_targets(&targets);
+        return;
+    }
 
-    if let Some(a) = cli.assembly_out {
-        targetspec.intermediate = a;
+    // a custom target is named after its file in messages
+    let target_name = cli
+        .custom_target
+        .clone()
+        .or(cli.target)
+        .or_else(|| manifest.as_ref().and_then(|m| m.project.target.clone()))
+        .unwrap_or("gcc".to_string());
+    let target_toml = match &cli.custom_target {
+        Some(path) => fs::read_to_string(path)
+            .map_err(|e| format!("Could not read {path}: {e}"))
+            .and_then(|text| TargetDesc::parse(&text).map_err(|e| format!("{path}: {e}"))),
+        None => targets.get(&target_name),
     }
+    .unwrap_or_else(|e| {
+        eprintln!("error: {e}");
+        exit(1);
+    });
+
+    let targetspec = target_toml.into_host().unwrap_or_else(|| {
+        eprintln!(
+            "error: Your platform ({}) does not yet support the {target_name} target",
+            env::consts::FAMILY.to_uppercase()
+        );
+        exit(1);
+    });
+
+    let options = CompileOptions {
+        typecheck: cli.typecheck.unwrap_or_default(),
+        include_paths: include_paths(cli.include, manifest.as_ref()),
+    };
 
-    if let Some(f) = cli.inputfile {
-        build_file(&f, &targetspec.intermediate, &targetspec.backend);
-        stdlib(&targetspec.stdlib.unwrap_or("".to_string()));
+    // builds with their own builddir do not get in each others way
+    let output = cli.output.or_else(|| manifest.as_ref().map(|m| m.output()));
+    let builddir = cli
+        .build_dir
+        .or_else(|| manifest.as_ref().map(|m| m.root.join("build")))
+        .or_else(|| {
+            let name = output.as_ref()?.file_name()?;
+            Some(Path::new("build").join(name))
+        })
+        .unwrap_or(PathBuf::from("build"));
+    let paths = BuildPaths::new(&targetspec, builddir, cli.assembly_out, output);
+
+    let inputfile = match (&manifest, &cli.command) {
+        (Some(m), Some(Project::Clean { .. })) => {
+            clean(&paths, &m.native_sources());
+            return;
+        }
+        (Some(m), _) => Some(m.entry()),
+        (None, _) => cli.inputfile,
+    };
+
+    if let Some(f) = inputfile {
+        let native = manifest
+            .iter()
+            .flat_map(|m| m.native_sources())
+            .collect::<Vec<_>>();
+        let run = cli.is_run || matches!(cli.command, Some(Project::Run { .. }));
+
+        // better to find out now than from a confusing bash error halfway through,
+        // but only about the steps that will run
+        let mut commands = vec![];
         if !cli.assembly_only {
-            assemble(&targetspec.assemble.unwrap_or("".to_string()));
+            commands.extend([&targetspec.stdlib, &targetspec.assemble]);
+            if !native.is_empty() {
+                commands.push(&targetspec.native);
+            }
             if !cli.object_only {
-                link(&targetspec.link.unwrap_or("".to_string()));
+                commands.push(&targetspec.link);
+            }
+        }
+        if run && targetspec.backend != "vm" {
+            commands.push(&targetspec.interpret);
+        }
+        let commands: Vec<&str> = commands.into_iter().flatten().map(String::as_str).collect();
+        let mut problems = targetspec.check_dependencies(&commands);
+        if !native.is_empty() && targetspec.native.is_none() {
+            problems.push("it can not link in the native sources of the project".to_string());
+        }
+        if !problems.is_empty() {
+            fail(
+                Stage::Preflight,
+                &format!(
+                    "the {target_name} target can not be used, {}",
+                    problems.join(", ")
+                ),
+            );
+        }
+
+        for dir in [Some(paths.builddir.as_path()), paths.output.parent()]
+            .into_iter()
+            .flatten()
+        {
+            if let Err(e) = fs::create_dir_all(dir) {
+                fail(
+                    Stage::Compile,
+                    &format!("could not create {}: {e}", dir.display()),
+                );
             }
         }
-        if cli.is_run {
-            interpret(
-                targetspec
-                    .interpret
-                    .unwrap_or("./a.out".to_string())
-                    .as_ref(),
+        if let Err(e) = paths.write_stdlib() {
+            fail(
+                Stage::Stdlib,
+                &format!("could not write {}: {e}", paths.stdlib_dir.display()),
             );
         }
+        if !build_file(&f, &paths.intermediate, &targetspec.backend, &options) {
+            exit(Stage::Compile as i32); // the diagnostics already say what is wrong
+        }
+        let expand = |cmd: &Option<String>| cmd.as_deref().map(|c| paths.expand(c));
+        if !cli.assembly_only {
+            step(Stage::Stdlib, expand(&targetspec.stdlib).as_deref());
+            let mut objects = String::new();
+            for (n, src) in native.iter().enumerate() {
+                let cmd = paths.expand_native(targetspec.native.as_deref().unwrap_or(""), n, src);
+                step(Stage::Native, Some(&cmd));
+                objects += &format!(" {}", quote(&paths.native_object(n, src)));
+            }
+
+            step(Stage::Assemble, expand(&targetspec.assemble).as_deref());
+            if !cli.object_only {
+                let link = expand(&targetspec.link).map(|l| format!("{l}{objects}"));
+                step(Stage::Link, link.as_deref());
+            }
+        }
+        if run {
+            exit(execute(&targetspec, &paths));
+        }
     } else {
-        repl(targetspec);
+        repl(options);
     }
 }
@@ -0,0 +1,99 @@
+/*
+* Copyright (C) 2025 Filip Chovanec
+*
+* This program is free software: you can redistribute it and/or modify
+* it under the terms of the GNU General Public License as published by
+* the Free Software Foundation, either version 3 of the License, or
+* (at your option) any later version.
+*
+* This program is distributed in the hope that it will be useful,
+* but WITHOUT ANY WARRANTY; without even the implied warranty of
+* MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+* GNU General Public License for more details.
+*
+* You should have received a copy of the GNU General Public License
+* along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+// dryft.toml describes a project, so dryftc build/run/clean know what to do without any arguments
+//
+// [project]
+// name = "fizzbuzz"
+// entry = "src/main.dry"
+// target = "gcc"
+// include = ["lib"]
+// native = ["native/extra.c"]
+// output = "fizzbuzz"
+
+use serde::Deserialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub const MANIFEST: &str = "dryft.toml";
+
+#[derive(Debug, Deserialize)]
+pub struct Manifest {
+    pub project: Project,
+    #[serde(skip)]
+    pub root: PathBuf, // directory the manifest is in, every path in it is relative to this
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Project {
+    pub name: String,
+    pub entry: PathBuf,
+    pub target: Option<String>, // the --target flag still wins
+    #[serde(default)]
+    pub include: Vec<PathBuf>, // searched for includes after -I and DRYFT_PATH
+    #[serde(default)]
+    pub native: Vec<PathBuf>, // C sources compiled and linked into the executable
+    pub output: Option<PathBuf>, // the project name by default
+}
+
+impl Manifest {
+    pub fn parse(text: &str, root: &Path) -> Result<Self, String> {
+        let mut manifest: Manifest = toml::from_str(text).map_err(|e| e.to_string())?;
+        manifest.root = root.to_path_buf();
+        Ok(manifest)
+    }
+
+    // the closest dryft.toml in dir or any of its parents
+    pub fn find(dir: &Path) -> Result<Self, String> {
+        let Some(root) = dir.ancestors().find(|d| d.join(MANIFEST).is_file()) else {
+            return Err(format!(
+                "Could not find {MANIFEST} in {} or any parent directory",
+                dir.display()
+            ));
+        };
+        let path = root.join(MANIFEST);
+        let text = fs::read_to_string(&path).map_err(|e| format!("{}: {e}", path.display()))?;
+        Self::parse(&text, root).map_err(|e| format!("{}: {e}", path.display()))
+    }
+
+    pub fn entry(&self) -> PathBuf {
+        self.root.join(&self.project.entry)
+    }
+
+    pub fn include_paths(&self) -> Vec<PathBuf> {
+        self.project
+            .include
+            .iter()
+            .map(|p| self.root.join(p))
+            .collect()
+    }
+
+    pub fn native_sources(&self) -> Vec<PathBuf> {
+        self.project
+            .native
+            .iter()
+            .map(|p| self.root.join(p))
+            .collect()
+    }
+
+    pub fn output(&self) -> PathBuf {
+        let output = self.project.output.clone();
+        self.root
+            .join(output.unwrap_or_else(|| self.project.name.clone().into()))
+    }
+}
@@ -0,0 +1,520 @@
+/*
+* Copyright (C) 2025 Filip Chovanec
+*
+* This program is free software: you can redistribute it and/or modify
+* it under the terms of the GNU General Public License as published by
+* the Free Software Foundation, either version 3 of the License, or
+* (at your option) any later version.
+*
+* This program is distributed in the hope that it will be useful,
+* but WITHOUT ANY WARRANTY; without even the implied warranty of
+* MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+* GNU General Public License for more details.
+*
+* You should have received a copy of the GNU General Public License
+* along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use crate::ast::{
+    Block, Body, Definition, Linkin, Literal, MethodDef, Node, NodeKind, Program, Signature,
+};
+use crate::diagnostic::{Diagnostic, Severity};
+use crate::lexer::{lex, Token, TokenKind};
+use crate::state::{DefinitionTypes, MethodClass, ValueTypes};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// so std/ can be included from anywhere, a std/ found on the search path still comes first
+const BUILTIN_STD: &[(&str, &str)] = &[("std/io", include_str!("../std/io.dry"))];
+
+const MISPLACED_ANNOTATION: &str = "Annotations can only be placed before fun: or act:";
+
+const OPENERS: &[&str] = &[
+    "fun", "fun:", "act", "act:", "then", "then:", "elect", "elect:", "when", "when:", "loop",
+    "loop:", "cycle", "cycle:", "module",
+];
+
+const CLOSERS: &[&str] = &[
+    ";", "end", ":fun", ":act", ":then", ":elect", ":when", ":loop", ":cycle", ":module",
+];
+
+// which block a named terminator is supposed to end
+fn terminated_by(closer: &str) -> Option<DefinitionTypes> {
+    match closer {
+        ":fun" => Some(DefinitionTypes::Function),
+        ":act" => Some(DefinitionTypes::Action),
+        ":then" => Some(DefinitionTypes::Then),
+        ":elect" | ":when" => Some(DefinitionTypes::Elect),
+        ":loop" | ":cycle" => Some(DefinitionTypes::Loop),
+        ":module" => Some(DefinitionTypes::Module),
+        _ => None,
+    }
+}
+
+// true if the code leaves a block, string, comment or annotation open, so more of it is coming
+// anything else that fails to lex is for the parser to report
+pub fn unfinished(code: &str) -> bool {
+    let tokens = match lex("<repl>", code) {
+        Ok(tokens) => tokens,
+        Err(e) => return e.message.starts_with("Unterminated"),
+    };
+
+    let mut depth = 0isize;
+    let mut keywords = tokens.iter().filter(|t| t.kind == TokenKind::Keyword);
+    while let Some(token) = keywords.next() {
+        if token.text == "linkin" {
+            // the fun or act after it is a class, linked in methods have no body
+            keywords.next();
+        } else if OPENERS.contains(&token.text.as_ref()) {
+            depth += 1;
+        } else if CLOSERS.contains(&token.text.as_ref()) {
+            depth -= 1;
+        }
+    }
+    depth > 0
+}
+
+pub struct Parser {
+    tokens: VecDeque<Token>, // still to be parsed, includes are spliced in at the front
+    open: Vec<DefinitionTypes>, // blocks being parsed right now, so we know what to skip after an error
+    annotation: Option<Token>,  // last annotation seen, belongs to the definition that follows it
+    pub sources: HashMap<String, String>, // contents of every file read so far, for diagnostics
+    pub diagnostics: Vec<Diagnostic>,
+    pub log_tokens: Vec<String>,     // purely for debugging usecases
+    pub include_paths: Vec<PathBuf>, // searched for includes after the directory of the including file
+    included: HashSet<PathBuf>, // every file spliced in so far, the same file is only included once
+}
+
+impl Parser {
+    pub fn new(file: &str, code: &str) -> Result<Self, Diagnostic> {
+        let tokens = lex(file, code)?;
+        Ok(Self {
+            tokens: tokens.into(),
+            open: vec![],
+            annotation: None,
+            sources: HashMap::from([(file.to_string(), code.to_string())]),
+            diagnostics: vec![],
+            log_tokens: vec![],
+            include_paths: vec![],
+            included: fs::canonicalize(file).into_iter().collect(),
+        })
+    }
+
+    // the body of a fun or act is being parsed
+    fn in_method(&self) -> bool {
+        self.open
+            .iter()
+            .any(|d| matches!(d, DefinitionTypes::Function | DefinitionTypes::Action))
+    }
+
+    fn error(&self, token: &Token, msg: &str) -> Diagnostic {
+        let src = self
+            .sources
+            .get(&token.span.file)
+            .map(String::as_str)
+            .unwrap_or("");
+        Diagnostic::new(Severity::Error, token.span.clone(), token.word, msg, src)
+    }
+
+    // parse everything, errors are collected and the broken definitions left out
+    pub fn program(&mut self) -> Program {
+        Program {
+            definitions: self.definitions(None),
+        }
+    }
+
+    // next meaningful token, comments are dropped and includes are spliced in right here
+    fn next(&mut self) -> Result<Option<Token>, Diagnostic> {
+        while let Some(token) = self.tokens.pop_front() {
+            match token.kind {
+                TokenKind::Comment => {}
+                TokenKind::Annotation if self.in_method() => {
+                    return Err(self.error(&token, MISPLACED_ANNOTATION))
+                }
+                TokenKind::Annotation => self.annotation = Some(token),
+                TokenKind::String => return Ok(Some(token)),
+                TokenKind::Word | TokenKind::Integer | TokenKind::Keyword => {
+                    self.log_tokens.push(token.text.clone());
+                    if matches!(token.text.as_ref(), "include" | "include:") {
+                        self.include(&token)?;
+                    } else {
+                        return Ok(Some(token));
+                    }
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    fn include(&mut self, keyword: &Token) -> Result<(), Diagnostic> {
+        let Some(name) = self.next()? else {
+            return Err(self.error(keyword, "Expected a file to include"));
+        };
+
+        let file = format!("{}.dry", name.text);
+        // next to the including file, code that is not from a file looks in the working directory
+        let here = if name.span.file.starts_with('<') {
+            PathBuf::new()
+        } else {
+            let parent = Path::new(&name.span.file).parent();
+            parent.map(Path::to_path_buf).unwrap_or_default()
+        };
+        let searched: Vec<PathBuf> = std::iter::once(here.join(&file))
+            .chain(self.include_paths.iter().map(|dir| dir.join(&file)))
+            .collect();
+        let found = searched.iter().find_map(|path| {
+            let content = String::from_utf8(fs::read(path).ok()?).ok()?;
+            Some((
+                path.display().to_string(),
+                fs::canonicalize(path).ok()?,
+                content,
+            ))
+        });
+        let builtin = || {
+            let (_, content) = BUILTIN_STD.iter().find(|(n, _)| *n == name.text)?;
+            let pat = format!("<builtin>/{file}");
+            Some((pat.clone(), PathBuf::from(pat), content.to_string()))
+        };
+        let Some((pat, resolved, included_content)) = found.or_else(builtin) else {
+            let mut e = self.error(&name, &format!("Could not locate include {file}"));
+            for path in searched.iter() {
+                e = e.with_note(&format!("looked for {}", path.display()));
+            }
+            return Err(e.with_note("and in the std/ built into dryftc"));
+        };
+
+        // diamonds would define everything twice, cycles would never end
+        if !self.included.insert(resolved) {
+            return Ok(());
+        }
+
+        // the included tokens are parsed next, then we continue where we left off
+        let tokens = lex(&pat, &included_content)?;
+        self.sources.insert(pat, included_content);
+        for token in tokens.into_iter().rev() {
+            self.tokens.push_front(token);
+        }
+        Ok(())
+    }
+
+    // skip the rest of the broken definition, everything above level stays open
+    fn recover(&mut self, level: usize) {
+        let mut depth = self.open.len().saturating_sub(level);
+        self.open.truncate(level);
+
+        while depth > 0 {
+            let Some(token) = self.tokens.pop_front() else {
+                break;
+            };
+            if token.kind != TokenKind::Keyword {
+                continue;
+            }
+            if OPENERS.contains(&token.text.as_ref()) {
+                depth += 1;
+            } else if CLOSERS.contains(&token.text.as_ref()) {
+                depth -= 1;
+            }
+        }
+    }
+
+    // top level definitions, or the contents of a module if we are given its keyword
+    fn definitions(&mut self, module: Option<&Token>) -> Vec<Definition> {
+        let level = self.open.len();
+        let mut definitions = vec![];
+
+        loop {
+            let token = match self.next() {
+                Ok(Some(token)) => token,
+                Ok(None) => {
+                    if let Some(keyword) = module {
+                        let e = self.error(keyword, "Unterminated module, expected :module");
+                        self.diagnostics.push(e);
+                    }
+                    break;
+                }
+                Err(e) => {
+                    self.diagnostics.push(e);
+                    self.recover(level);
+                    continue;
+                }
+            };
+
+            if module.is_some() && matches!(token.text.as_ref(), ";" | "end" | ":module") {
+                self.open.pop();
+                break;
+            }
+
+            match self.definition(token) {
+                Ok(Some(d)) => definitions.push(d),
+                Ok(None) => {}
+                Err(e) => {
+                    self.diagnostics.push(e);
+                    self.recover(level);
+                }
+            }
+        }
+
+        self.leftover_annotation();
+        definitions
+    }
+
+    // an annotation with no definition after it
+    fn leftover_annotation(&mut self) {
+        if let Some(annotation) = self.annotation.take() {
+            let e = self.error(&annotation, MISPLACED_ANNOTATION);
+            self.diagnostics.push(e);
+        }
+    }
+
+    fn definition(&mut self, token: Token) -> Result<Option<Definition>, Diagnostic> {
+        let definition = match token.text.as_ref() {
+            _ if self.annotation.is_some()
+                && !matches!(token.text.as_ref(), "fun:" | "fun" | "act:" | "act") =>
+            {
+                let annotation = self.annotation.take().unwrap();
+                return Err(self.error(&annotation, MISPLACED_ANNOTATION));
+            }
+            "fun:" | "fun" => Definition::Fun(self.method(&token, DefinitionTypes::Function)?),
+            "act:" | "act" => Definition::Act(self.method(&token, DefinitionTypes::Action)?),
+            // this keyword is funamentally unsafe, consider adding changing to unsafe_linkin or something like that
+            // or put it into an unsafe: block
+            "linkin" => Definition::Linkin(self.linkin(&token)?),
+            "module" => {
+                self.open.push(DefinitionTypes::Module);
+                Definition::Module(self.definitions(Some(&token)))
+            }
+            "struct" => return Ok(None),
+            ";" | "end" => return Err(self.error(&token, " - Misplaced ;")),
+            c if CLOSERS.contains(&c) => return Err(self.error(&token, "no block to end")),
+            _ => return Err(self.error(&token, "Code outside of a definition")),
+        };
+        Ok(Some(definition))
+    }
+
+    fn linkin(&mut self, keyword: &Token) -> Result<Linkin, Diagnostic> {
+        let (Some(class), Some(name)) = (self.next()?, self.next()?) else {
+            return Err(self.error(keyword, "Expected a class and a name to link in"));
+        };
+
+        let class = match class.text.as_ref() {
+            "fun" => MethodClass::Function,
+            "act" => MethodClass::Action,
+            other => return Err(self.error(&class, &format!("Invalid link-in class {other}"))),
+        };
+
+        // the stack effect is optional and follows the name, like linkin act _x_print_int (Int ->)
+        let signature = match self.tokens.front() {
+            Some(t) if t.kind == TokenKind::Annotation => {
+                let annotation = self.tokens.pop_front().unwrap();
+                Some(self.signature(annotation)?)
+            }
+            _ => None,
+        };
+
+        Ok(Linkin {
+            class,
+            name: name.text.clone(),
+            token: name,
+            signature,
+        })
+    }
+
+    // turn the text of an annotation into a stack effect
+    fn signature(&self, annotation: Token) -> Result<Signature, Diagnostic> {
+        let Some((inputs, outputs)) = annotation.text.split_once("->") else {
+            return Err(self.error(&annotation, "Expected -> in the annotation"));
+        };
+
+        // lowercase names are type variables, the same name means the same type on both sides
+        let mut vars: Vec<String> = vec![];
+        let mut types = |side: &str| -> Result<Vec<ValueTypes>, Diagnostic> {
+            side.split_whitespace()
+                .map(|name| self.value_type(&annotation, name, &mut vars))
+                .collect()
+        };
+
+        Ok(Signature {
+            inputs: types(inputs)?,
+            outputs: types(outputs)?,
+            token: annotation.clone(),
+        })
+    }
+
+    fn value_type(
+        &self,
+        annotation: &Token,
+        name: &str,
+        vars: &mut Vec<String>,
+    ) -> Result<ValueTypes, Diagnostic> {
+        match name {
+            "Int" | "Number" => Ok(ValueTypes::Number),
+            "Bool" | "Binary" => Ok(ValueTypes::Binary),
+            "Text" | "Str" => Ok(ValueTypes::Text),
+            linear if linear.starts_with("Linear<") && linear.ends_with('>') => {
+                let inner = &linear["Linear<".len()..linear.len() - 1];
+                Ok(ValueTypes::Linear(Box::new(
+                    self.value_type(annotation, inner, vars)?,
+                )))
+            }
+            var if var.starts_with(|c: char| c.is_ascii_lowercase()) => {
+                let id = match vars.iter().position(|v| v == var) {
+                    Some(id) => id,
+                    None => {
+                        vars.push(var.to_string());
+                        vars.len() - 1
+                    }
+                };
+                Ok(ValueTypes::Var(id))
+            }
+            other => Err(self.error(annotation, &format!("Unknown type {other} in annotation"))),
+        }
+    }
+
+    fn method(&mut self, keyword: &Token, kind: DefinitionTypes) -> Result<MethodDef, Diagnostic> {
+        let signature = match self.annotation.take() {
+            Some(annotation) => Some(self.signature(annotation)?),
+            None => None,
+        };
+
+        let name = match self.next()? {
+            Some(name) if name.kind != TokenKind::Keyword => name,
+            Some(other) => return Err(self.error(&other, "No method name provided")),
+            None => return Err(self.error(keyword, "No method name provided")),
+        };
+
+        // one between the keyword and the name would end up on the next definition
+        if let Some(annotation) = self.annotation.take() {
+            return Err(self.error(&annotation, MISPLACED_ANNOTATION));
+        }
+
+        let body = self.body(&name, kind)?;
+
+        Ok(MethodDef {
+            name: name.text.clone(),
+            signature,
+            body,
+            token: name,
+        })
+    }
+
+    // everything up to the terminator of the block we just opened
+    fn body(&mut self, opener: &Token, kind: DefinitionTypes) -> Result<Body, Diagnostic> {
+        self.open.push(kind);
+        let mut nodes = vec![];
+
+        loop {
+            let Some(token) = self.next()? else {
+                let what: &'static str = self.open.last().unwrap().into();
+                return Err(self.error(opener, &format!("Unterminated {what} block, expected ;")));
+            };
+
+            if token.kind == TokenKind::Keyword {
+                match token.text.as_ref() {
+                    ";" | "end" => {
+                        self.open.pop();
+                        return Ok(Body { nodes, end: token });
+                    }
+                    c if CLOSERS.contains(&c) => {
+                        let expected = terminated_by(c).unwrap();
+                        if self.open.pop() != Some(expected) {
+                            let expected: &'static str = expected.into();
+                            return Err(
+                                self.error(&token, &format!("Misplaced {expected} block ending"))
+                            );
+                        }
+                        return Ok(Body { nodes, end: token });
+                    }
+                    _ => {}
+                }
+            }
+
+            if let Some(node) = self.node(token)? {
+                nodes.push(node);
+            }
+        }
+    }
+
+    // a single piece of code, blocks are parsed up to their terminator
+    fn node(&mut self, token: Token) -> Result<Option<Node>, Diagnostic> {
+        let kind = match token.kind {
+            TokenKind::String => NodeKind::Literal(Literal::Text(token.text.clone())),
+            TokenKind::Integer => NodeKind::Literal(Literal::Integer(token.text.clone())),
+            TokenKind::Keyword => match token.text.as_ref() {
+                "then" | "then:" => {
+                    NodeKind::Block(Block::Then(self.body(&token, DefinitionTypes::Then)?))
+                }
+                "elect" | "elect:" | "when" | "when:" => {
+                    NodeKind::Block(Block::When(self.body(&token, DefinitionTypes::Elect)?))
+                }
+                "loop" | "loop:" | "cycle" | "cycle:" => {
+                    NodeKind::Block(Block::Cycle(self.body(&token, DefinitionTypes::Loop)?))
+                }
+                "var" | "var:" => match self.next()? {
+                    Some(name) if name.kind == TokenKind::Word => {
+                        return Ok(Some(Node {
+                            kind: NodeKind::VarDef(name.text.clone()),
+                            token: name,
+                        }));
+                    }
+                    _ => return Err(self.error(&token, "Expected a variable name")),
+                },
+                "break" => NodeKind::Break,
+                "return" => NodeKind::Return,
+                "struct" => return Ok(None),
+                _ => return Err(self.error(&token, "Definitions can not be nested")),
+            },
+            TokenKind::Word => match token.text.as_ref() {
+                "true" => NodeKind::Literal(Literal::Binary(true)),
+                "false" => NodeKind::Literal(Literal::Binary(false)),
+                var if var.starts_with('$') => {
+                    NodeKind::VarRead(var.strip_prefix('$').unwrap().to_string())
+                }
+                setvar if setvar.len() > 1 && setvar.ends_with('!') => {
+                    NodeKind::VarWrite(setvar.strip_suffix('!').unwrap().to_string())
+                }
+                word => NodeKind::Word(word.to_string()),
+            },
+            TokenKind::Comment | TokenKind::Annotation => return Ok(None),
+        };
+
+        Ok(Some(Node { kind, token }))
+    }
+
+    // a line typed into the REPL, definitions mixed with code that runs right away
+    pub fn line(&mut self) -> (Vec<Definition>, Vec<Node>) {
+        let mut definitions = vec![];
+        let mut nodes = vec![];
+
+        loop {
+            let token = match self.next() {
+                Ok(Some(token)) => token,
+                Ok(None) => break,
+                Err(e) => {
+                    self.diagnostics.push(e);
+                    self.recover(0);
+                    continue;
+                }
+            };
+
+            let defines = self.annotation.is_some()
+                || matches!(
+                    token.text.as_ref(),
+                    "fun" | "fun:" | "act" | "act:" | "linkin" | "module"
+                )
+                || (token.kind == TokenKind::Keyword && CLOSERS.contains(&token.text.as_ref()));
+            let parsed = if defines {
+                self.definition(token).map(|d| definitions.extend(d))
+            } else {
+                self.node(token).map(|n| nodes.extend(n))
+            };
+            if let Err(e) = parsed {
+                self.diagnostics.push(e);
+                self.recover(0);
+            }
+        }
+
+        self.leftover_annotation();
+        (definitions, nodes)
+    }
+}
@@ -0,0 +1,161 @@
+/*
+* Copyright (C) 2025 Filip Chovanec
+*
+* This program is free software: you can redistribute it and/or modify
+* it under the terms of the GNU General Public License as published by
+* the Free Software Foundation, either version 3 of the License, or
+* (at your option) any later version.
+*
+* This program is distributed in the hope that it will be useful,
+* but WITHOUT ANY WARRANTY; without even the implied warranty of
+* MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+* GNU General Public License for more details.
+*
+* You should have received a copy of the GNU General Public License
+* along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+// the REPL keeps one compiler state and one machine alive, so every line builds on the ones before it
+// code always runs on the built-in vm, no matter the target
+
+use crate::backends::vm::VmBackend;
+use crate::backends::Backend;
+use crate::diagnostic::Diagnostic;
+use crate::frontend::compile_line;
+use crate::state::{stack_effect, CompileOptions, CompileState, MethodClass, TypeCheck};
+use crate::vm::{self, Machine, VmError};
+use std::io::Write;
+
+#[derive(Debug)]
+pub enum ReplError {
+    Compile(Vec<Diagnostic>),
+    Runtime(VmError),
+}
+
+pub struct Session {
+    pub cs: CompileState,
+    backend: Box<dyn Backend>,
+    pub bytecode: String, // everything compiled so far
+    pub machine: Machine,
+    lines: usize,
+}
+
+impl Session {
+    pub fn new(options: CompileOptions) -> Self {
+        Self {
+            cs: CompileState::with_options(options),
+            backend: Box::new(VmBackend::default()),
+            bytecode: String::new(),
+            machine: Machine::new(),
+            lines: 0,
+        }
+    }
+
+    // compile and run a line, returns the warnings it produced
+    // a line that fails to compile leaves no trace, one that fails at runtime empties the stack
+    pub fn eval(&mut self, line: &str, out: &mut dyn Write) -> Result<Vec<Diagnostic>, ReplError> {
+        let backup = self.cs.clone();
+        self.lines += 1;
+        let name = format!("__line_{}", self.lines);
+
+        let compiled = compile_line(&mut self.backend, &mut self.cs, line, &name)
+            .map(|code| format!("{}{code}", self.bytecode))
+            .map_err(ReplError::Compile)
+            .and_then(|bytecode| {
+                let program =
+                    vm::assemble(&self.backend.complete(&bytecode)).map_err(ReplError::Runtime)?;
+                Ok((bytecode, program))
+            });
+        let (bytecode, program) = match compiled {
+            Ok(compiled) => compiled,
+            Err(e) => {
+                self.cs = backup;
+                return Err(e);
+            }
+        };
+        self.bytecode = bytecode;
+
+        // whatever the line consumed from earlier lines is on the stack now, not before it
+        self.cs.voidstack[0].clear();
+        if self.cs.options.typecheck == TypeCheck::Off {
+            self.cs.typestack[0].clear();
+        }
+
+        if program.method(&name).is_some() {
+            if let Err(e) = self.machine.call(&program, &name, out) {
+                self.machine.stack.clear();
+                self.cs.typestack[0].clear();
+                return Err(ReplError::Runtime(e));
+            }
+        }
+        Ok(self.cs.diagnostics.clone())
+    }
+
+    // include a file as if it was typed in
+    pub fn load(&mut self, path: &str, out: &mut dyn Write) -> Result<Vec<Diagnostic>, ReplError> {
+        // include adds the extension itself
+        let path = path.strip_suffix(".dry").unwrap_or(path);
+        self.eval(&format!("include {path}"), out)
+    }
+
+    // forget every definition, variable and value
+    pub fn reset(&mut self) {
+        *self = Self::new(self.cs.options.clone());
+    }
+
+    // one value per line, bottom to top
+    // the inferred types only cover the top of the stack when typechecking is off
+    pub fn describe_stack(&self) -> String {
+        let stack = &self.machine.stack;
+        let types = self.cs.typestack.first().cloned().unwrap_or_default();
+        let untyped = stack.len().saturating_sub(types.len());
+        let mut text = format!("<{}>\n", stack.len());
+        for (i, value) in stack.iter().enumerate() {
+            match i.checked_sub(untyped).and_then(|t| types.get(t)) {
+                Some(t) => text += &format!("{value} : {}\n", self.cs.resolve(t)),
+                None => text += &format!("{value}\n"),
+            }
+        }
+        text
+    }
+
+    // every known word with its stack effect, sorted by name
+    // without typechecking only the declared stack effects are known
+    pub fn describe_words(&self) -> String {
+        let mut methods: Vec<_> = self.cs.methods.values().collect();
+        methods.sort_by(|a, b| a.name.cmp(&b.name));
+        let mut text = String::new();
+        for m in methods {
+            text += &format!("{} {}", class_keyword(m.class, &m.code), m.name);
+            if m.declared || self.cs.options.typecheck != TypeCheck::Off {
+                let inputs: Vec<_> = m.itypes.iter().rev().cloned().collect();
+                text += &format!(" {}", stack_effect(&inputs, &m.etypes));
+            }
+            text += "\n";
+        }
+        text
+    }
+
+    // the source of a word followed by the code it compiled to
+    pub fn describe_word(&self, name: &str) -> Option<String> {
+        let m = self.cs.methods.get(name)?;
+        Some(format!(
+            "# {}:{} #\n{} {}\n\n{}\n",
+            m.span.file,
+            m.span.start_line,
+            class_keyword(m.class, &m.code),
+            m.source,
+            m.code.trim_end()
+        ))
+    }
+}
+
+fn class_keyword(class: MethodClass, code: &str) -> &'static str {
+    // linked in methods have no body of their own
+    match (class, code == "LINKED IN") {
+        (MethodClass::Function, false) => "fun",
+        (MethodClass::Action, false) => "act",
+        (MethodClass::Function, true) => "linkin fun",
+        (MethodClass::Action, true) => "linkin act",
+    }
+}
@@ -15,111 +15,208 @@
 * along with this program.  If not, see <https://www.gnu.org/licenses/>.
 */
 
-use std::collections::HashMap;
-use strum_macros::{Display, IntoStaticStr};
-
-#[derive(Debug, PartialEq, IntoStaticStr)]
+use crate::ast::Signature;
+use crate::diagnostic::{Diagnostic, Severity, Span};
+use crate::lexer::Token;
+use clap::ValueEnum;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::path::PathBuf;
+use strum_macros::IntoStaticStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, IntoStaticStr)]
 pub enum DefinitionTypes {
     Function,
     Action,
-    Linkin,
     Then,
     Elect,
-    Include,
     Loop,
-    Variable,
     Module,
-    Negative, // purely comparative, not actually constructed by code
 }
 
-#[derive(Clone, PartialEq, Debug, Display)]
+#[derive(Clone, PartialEq, Debug)]
 pub enum ValueTypes {
     Number,
     Text,
     Binary,
     Method(Vec<ValueTypes>, Vec<ValueTypes>),
-    Fake, // purely comparative, not actually represented in dryft
+    Var(usize),              // stands for any type until unified with something concrete
+    Linear(Box<ValueTypes>), // has to be consumed exactly once
+    Fake,                    // purely comparative, not actually represented in dryft
+}
+
+impl fmt::Display for ValueTypes {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ValueTypes::Number => write!(f, "Number"),
+            ValueTypes::Text => write!(f, "Text"),
+            ValueTypes::Binary => write!(f, "Binary"),
+            ValueTypes::Method(..) => write!(f, "Method"),
+            ValueTypes::Linear(t) => write!(f, "Linear<{t}>"),
+            ValueTypes::Var(id) => {
+                // a, b, ... z, ba, bb, ... like annotations would name them
+                let mut name = vec![];
+                let mut n = *id;
+                loop {
+                    name.push((b'a' + (n % 26) as u8) as char);
+                    n /= 26;
+                    if n == 0 {
+                        break;
+                    }
+                }
+                write!(f, "{}", name.iter().rev().collect::<String>())
+            }
+            ValueTypes::Fake => write!(f, "Fake"),
+        }
+    }
+}
+
+impl ValueTypes {
+    fn mentions(&self, id: usize) -> bool {
+        match self {
+            ValueTypes::Var(v) => *v == id,
+            ValueTypes::Method(i, o) => i.iter().chain(o.iter()).any(|t| t.mentions(id)),
+            ValueTypes::Linear(t) => t.mentions(id),
+            _ => false,
+        }
+    }
+
+    fn holds_linear(&self) -> bool {
+        match self {
+            ValueTypes::Linear(_) => true,
+            ValueTypes::Method(i, o) => i.iter().chain(o.iter()).any(|t| t.holds_linear()),
+            _ => false,
+        }
+    }
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum MethodClass {
     Function,
     Action,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Method {
     pub name: String,
     pub code: String,
     pub class: MethodClass,
     pub itypes: Vec<ValueTypes>,
     pub etypes: Vec<ValueTypes>,
+    pub declared: bool, // the stack effect comes from an annotation, it holds even without typechecking
+    pub pure: bool,     // decided when it is compiled, calls stay bound to the definition they saw
+    pub span: Span,     // from the name to the end of the definition
+    pub source: String, // the text at span, kept since REPL lines replace each other in sources
 }
 
-#[derive(Debug)]
+// how strictly the types inferred by the compiler are enforced
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum TypeCheck {
+    Off,
+    Warn,
+    Strict,
+}
+
+// the typesystem feature only decides what a plain build does by default
+impl Default for TypeCheck {
+    fn default() -> Self {
+        if cfg!(feature = "typesystem") {
+            TypeCheck::Strict
+        } else {
+            TypeCheck::Off
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct CompileOptions {
+    pub typecheck: TypeCheck,
+    pub include_paths: Vec<PathBuf>, // searched for includes after the directory of the including file
+}
+
+// formats types bottom to top, like annotations do
+pub fn stack_effect(inputs: &[ValueTypes], outputs: &[ValueTypes]) -> String {
+    let mut words: Vec<String> = inputs.iter().map(|t| t.to_string()).collect();
+    words.push("->".to_string());
+    words.extend(outputs.iter().map(|t| t.to_string()));
+    format!("({})", words.join(" "))
+}
+
+// how deep each stack was, see CompileState::depth()
+#[derive(Debug, Clone, Copy)]
+pub struct Depth {
+    defnstack: usize,
+    bodystack: usize,
+    varscopes: usize,
+    typestack: usize,
+    voidstack: usize,
+}
+
+#[derive(Debug, Clone)]
 pub struct CompileState {
+    pub options: CompileOptions,
     pub out: Option<String>,     // access after compile() has been called
     pub log_tokens: Vec<String>, // purely for debugging usecases
+    pub diagnostics: Vec<Diagnostic>,
 
     pub methods: HashMap<String, Method>,
     // pub functions: HashMap<String, String>,
     // pub actions: HashMap<String, String>,
-    pub word: String,
-
     pub defnstack: Vec<DefinitionTypes>,
-    pub metastack: Vec<Vec<String>>,
     pub bodystack: Vec<String>,
     pub varscopes: Vec<HashMap<String, ValueTypes>>,
     pub typestack: Vec<Vec<ValueTypes>>,
     pub voidstack: Vec<Vec<ValueTypes>>,
+    pub bindings: HashMap<usize, ValueTypes>, // what each unified type variable stands for
+    pub typevars: usize,                      // type variables handed out so far
+    pub unrestricted: HashSet<usize>, // type variables that got copied or dropped, so they can not be linear
+    pub moved: HashSet<String>,       // linear variables that were already read
+    pub loop_scopes: Vec<usize>,      // varscope of every cycle we are in, innermost last
+    pub elect_exits: Vec<Vec<HashSet<String>>>, // moved as each then of an elect leaves it
+    pub diverged: bool, // a break or return came first, so the code being compiled never runs
+    pub method_scope: usize, // varscope of the method being compiled
 
-    pub iscomment: bool,
-    pub isstring: bool,
-    pub newstring: String,
-
-    pub isannotation: bool,
-    pub annotation_content: String,
-
-    pub prepend: String,
-    pub prepend_remaining: usize, // characters remaining from current prepended content
-
-    pub linenumber: isize,
     pub tokenumber: isize,
-    pub token_line: isize,  // line where current token started
-    pub token_file: String, // file where current token started
-    pub current_file: String,
-    pub file_stack: Vec<(String, isize)>, // stack of (filename, line_number) for includes
+    pub token_span: Span, // where the current token was read from
+    pub sources: HashMap<String, String>, // contents of every file read so far, for diagnostics
+}
+
+impl Default for CompileState {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl CompileState {
     pub fn new() -> Self {
+        Self::with_options(CompileOptions::default())
+    }
+
+    pub fn with_options(options: CompileOptions) -> Self {
         Self {
+            options,
             out: None,
             log_tokens: vec![],
-            word: String::new(),
+            diagnostics: vec![],
             methods: HashMap::new(),
 
-            isannotation: false,
-            annotation_content: String::new(),
-
             defnstack: vec![],
-            metastack: vec![],
             bodystack: vec![String::new()],
             varscopes: vec![HashMap::new()],
             typestack: vec![],
             voidstack: vec![],
+            bindings: HashMap::new(),
+            typevars: 0,
+            unrestricted: HashSet::new(),
+            moved: HashSet::new(),
+            loop_scopes: vec![],
+            elect_exits: vec![],
+            diverged: false,
+            method_scope: 0,
 
-            iscomment: false,
-            isstring: false,
-            newstring: String::new(),
-            prepend: String::new(),
-            prepend_remaining: 0,
-            linenumber: 1,
             tokenumber: 0,
-            token_line: 1,
-            token_file: "<main>".to_string(),
-            current_file: "<main>".to_string(),
-            file_stack: vec![],
+            token_span: Span::default(),
+            sources: HashMap::new(),
         }
     }
 
@@ -138,59 +235,316 @@ impl CompileState {
         }
     }
 
-    pub fn pop_type(&mut self) -> ValueTypes {
-        if cfg!(not(feature = "typesystem")) {
-            return ValueTypes::Fake;
+    // strict mode aborts the definition, warn mode just reports and carries on
+    pub fn type_error(&mut self, msg: &str) -> Result<(), Diagnostic> {
+        match self.options.typecheck {
+            TypeCheck::Strict => Err(self.error(msg)),
+            TypeCheck::Warn => {
+                self.warning(msg);
+                Ok(())
+            }
+            TypeCheck::Off => Ok(()),
+        }
+    }
+
+    pub fn fresh_type(&mut self) -> ValueTypes {
+        self.typevars += 1;
+        ValueTypes::Var(self.typevars)
+    }
+
+    // follow bound type variables until we reach something concrete or unbound
+    pub fn resolve(&self, t: &ValueTypes) -> ValueTypes {
+        match t {
+            ValueTypes::Var(id) => match self.bindings.get(id) {
+                Some(bound) => self.resolve(bound),
+                None => t.clone(),
+            },
+            ValueTypes::Method(i, o) => ValueTypes::Method(
+                i.iter().map(|t| self.resolve(t)).collect(),
+                o.iter().map(|t| self.resolve(t)).collect(),
+            ),
+            ValueTypes::Linear(t) => ValueTypes::Linear(Box::new(self.resolve(t))),
+            _ => t.clone(),
+        }
+    }
+
+    // make both types the same, binding type variables where needed
+    pub fn unify(&mut self, a: &ValueTypes, b: &ValueTypes) -> bool {
+        let a = self.resolve(a);
+        let b = self.resolve(b);
+        match (&a, &b) {
+            (ValueTypes::Fake, _) | (_, ValueTypes::Fake) => true,
+            (ValueTypes::Var(x), ValueTypes::Var(y)) if x == y => true,
+            (ValueTypes::Var(x), t) | (t, ValueTypes::Var(x)) => {
+                if t.mentions(*x) {
+                    return false;
+                }
+                if self.unrestricted.contains(x) {
+                    if t.holds_linear() {
+                        return false;
+                    }
+                    if let ValueTypes::Var(y) = t {
+                        self.unrestricted.insert(*y);
+                    }
+                }
+                self.bindings.insert(*x, t.clone());
+                true
+            }
+            (ValueTypes::Linear(x), ValueTypes::Linear(y)) => self.unify(x, y),
+            (ValueTypes::Method(ai, ao), ValueTypes::Method(bi, bo)) => {
+                ai.len() == bi.len()
+                    && ao.len() == bo.len()
+                    && ai
+                        .iter()
+                        .zip(bi.iter())
+                        .chain(ao.iter().zip(bo.iter()))
+                        .all(|(x, y)| self.unify(x, y))
+            }
+            _ => a == b,
+        }
+    }
+
+    fn unify_all(&mut self, a: &[ValueTypes], b: &[ValueTypes]) -> bool {
+        a.len() == b.len() && a.iter().zip(b.iter()).all(|(x, y)| self.unify(x, y))
+    }
+
+    // give every type variable in a polymorphic stack effect a fresh name, so separate uses do not constrain each other
+    pub fn instantiate(
+        &mut self,
+        inputs: &[ValueTypes],
+        outputs: &[ValueTypes],
+    ) -> (Vec<ValueTypes>, Vec<ValueTypes>) {
+        let mut fresh = HashMap::new();
+        let mut rename = |cs: &mut Self, types: &[ValueTypes]| -> Vec<ValueTypes> {
+            types
+                .iter()
+                .map(|t| cs.rename_vars(t, &mut fresh))
+                .collect()
+        };
+        let i = rename(self, inputs);
+        let o = rename(self, outputs);
+        (i, o)
+    }
+
+    fn rename_vars(
+        &mut self,
+        t: &ValueTypes,
+        fresh: &mut HashMap<usize, ValueTypes>,
+    ) -> ValueTypes {
+        match t {
+            ValueTypes::Var(id) => match fresh.get(id) {
+                Some(v) => v.clone(),
+                None => {
+                    let v = self.fresh_type();
+                    if let ValueTypes::Var(new) = v {
+                        if self.unrestricted.contains(id) {
+                            self.unrestricted.insert(new);
+                        }
+                    }
+                    fresh.insert(*id, v.clone());
+                    v
+                }
+            },
+            ValueTypes::Method(i, o) => ValueTypes::Method(
+                i.iter().map(|t| self.rename_vars(t, fresh)).collect(),
+                o.iter().map(|t| self.rename_vars(t, fresh)).collect(),
+            ),
+            ValueTypes::Linear(t) => ValueTypes::Linear(Box::new(self.rename_vars(t, fresh))),
+            _ => t.clone(),
+        }
+    }
+
+    pub fn is_linear(&self, t: &ValueTypes) -> bool {
+        matches!(self.resolve(t), ValueTypes::Linear(_))
+    }
+
+    // for words that duplicate or discard a value, what stays generic may never become linear
+    pub fn expect_unrestricted(&mut self, t: &ValueTypes, action: &str) -> Result<(), Diagnostic> {
+        match self.resolve(t) {
+            ValueTypes::Linear(_) => self.type_error(&format!(
+                "Can not {action} a linear value of type {}, it has to be consumed exactly once",
+                self.resolve(t)
+            )),
+            ValueTypes::Var(id) => {
+                self.unrestricted.insert(id);
+                Ok(())
+            }
+            _ => Ok(()),
         }
+    }
 
-        self.typestack
-            .last_mut()
-            .expect("should implement pulling types from previous frame")
-            .pop()
-            .expect("type stack should not be empty when popping")
+    // linear variables have to be read exactly once before they go out of scope
+    pub fn close_varscope(&mut self) -> Result<(), Diagnostic> {
+        let scope = self.varscopes.pop().unwrap();
+        let mut names: Vec<&String> = scope.keys().collect();
+        names.sort();
+        for name in names {
+            // code after a break or return is not where the scope ends
+            let used = self.moved.remove(name);
+            if self.is_linear(&scope[name]) && !used && !self.diverged {
+                self.type_error(&format!("Linear variable {name} is never used"))?;
+            }
+        }
+        Ok(())
     }
 
-    pub fn expect_types(&mut self, expected: &[ValueTypes]) {
-        if cfg!(not(feature = "typesystem")) {
-            return;
+    // jumping out of every scope from the given one on, what they hold has to be used up by now
+    pub fn leave_scopes(&mut self, from: usize, how: &str) -> Result<(), Diagnostic> {
+        let mut left: Vec<String> = self.varscopes[from..]
+            .iter()
+            .flat_map(|scope| scope.iter())
+            .filter(|(name, t)| self.is_linear(t) && !self.moved.contains(*name))
+            .map(|(name, _)| name.clone())
+            .collect();
+        left.sort();
+        if let Some(name) = left.first() {
+            self.type_error(&format!(
+                "Linear variable {name} is never used before {how}"
+            ))?;
         }
+        self.diverged = true;
+        Ok(())
+    }
 
-        let stack = self.typestack.last_mut().unwrap();
+    // branches that come together again have to agree on which linear variables they used up
+    pub fn expect_same_moved(&mut self, other: &HashSet<String>) -> Result<(), Diagnostic> {
+        let mut differ: Vec<&String> = self.moved.symmetric_difference(other).collect();
+        differ.sort();
+        if let Some(name) = differ.first() {
+            let msg = format!("Linear variable {name} has to be used on every branch or on none");
+            self.type_error(&msg)?;
+        }
+        Ok(())
+    }
+
+    pub fn pop_type(&mut self) -> Result<ValueTypes, Diagnostic> {
+        if self.options.typecheck == TypeCheck::Off {
+            return Ok(ValueTypes::Fake);
+        }
+
+        // TODO: implement pulling types from previous frame
+        match self.typestack.last_mut().and_then(|frame| frame.pop()) {
+            Some(t) => Ok(t),
+            None => {
+                // consumed from outside the definition, whatever it is gets decided by its uses
+                let t = self.fresh_type();
+                self.voidstack.last_mut().unwrap().push(t.clone());
+                Ok(t)
+            }
+        }
+    }
+
+    pub fn expect_types(&mut self, expected: &[ValueTypes]) -> Result<(), Diagnostic> {
+        if self.options.typecheck == TypeCheck::Off {
+            return Ok(());
+        }
 
         for ex in expected.iter() {
-            if let Some(found) = stack.pop() {
-                if &found != ex {
-                    self.throw_error(&format!("Type mismatch : Expected {ex}, found {found}"))
+            if let Some(found) = self.typestack.last_mut().unwrap().pop() {
+                if !self.unify(ex, &found) {
+                    let (ex, found) = (self.resolve(ex), self.resolve(&found));
+                    let msg = match ex {
+                        ValueTypes::Var(id) if self.unrestricted.contains(&id) => format!(
+                            "Type mismatch : Expected a value that can be copied or dropped, found {found}"
+                        ),
+                        ex => format!("Type mismatch : Expected {ex}, found {found}"),
+                    };
+                    self.type_error(&msg)?;
                 }
                 continue;
             }
             self.voidstack.last_mut().unwrap().push(ex.clone());
         }
+        Ok(())
     }
 
-    pub fn expect_no_type_footprint(&mut self) {
-        if cfg!(not(feature = "typesystem")) {
-            return;
-        }
-
+    pub fn expect_no_type_footprint(&mut self) -> Result<(), Diagnostic> {
         let ret = self.typestack.pop().unwrap();
         let void = self.voidstack.pop().unwrap();
 
-        if void != ret {
-            self.throw_error(&format!("Block returns {:?}, but consumes {:?}", ret, void));
+        if self.options.typecheck == TypeCheck::Off {
+            return Ok(());
+        }
+
+        if !self.unify_all(&void, &ret) {
+            let ret: Vec<ValueTypes> = ret.iter().map(|t| self.resolve(t)).collect();
+            let void: Vec<ValueTypes> = void.iter().map(|t| self.resolve(t)).collect();
+            self.type_error(&format!("Block returns {:?}, but consumes {:?}", ret, void))?;
         }
 
-        self.expect_types(&void);
+        self.expect_types(&void)
     }
 
-    // checks that the action is not called inside any function scope
-    pub fn before_action(&self) {
+    // compare what the body of a method did to the types its annotation promised
+    // inputs and outputs are the instantiated signature, see instantiate()
+    pub fn expect_signature(
+        &mut self,
+        signature: &Signature,
+        inputs: &[ValueTypes],
+        outputs: &[ValueTypes],
+    ) -> Result<(), Diagnostic> {
+        if self.options.typecheck == TypeCheck::Off {
+            return Ok(());
+        }
+
+        let ret = self.typestack.last().unwrap().clone();
+        let void = self.voidstack.last().unwrap().clone();
+
+        // declared type variables must stay generic, the body may not pick a type for them
+        let mut declared: Vec<usize> = vec![];
+        for t in inputs.iter().chain(outputs.iter()) {
+            if let ValueTypes::Var(id) = t {
+                if !declared.contains(id) {
+                    declared.push(*id);
+                }
+            }
+        }
+        let matches = void.is_empty() && self.unify_all(&ret, outputs);
+        let count = declared.len();
+        let mut generic: Vec<usize> = vec![];
+        for id in declared {
+            if let ValueTypes::Var(v) = self.resolve(&ValueTypes::Var(id)) {
+                if !generic.contains(&v) {
+                    generic.push(v);
+                    continue;
+                }
+            }
+            generic.clear();
+            break;
+        }
+        let general = matches && generic.len() == count;
+        if general {
+            return Ok(());
+        }
+
+        // anything consumed beyond the declared inputs was below them on the stack
+        let mut consumed: Vec<ValueTypes> = void.iter().rev().map(|t| self.resolve(t)).collect();
+        consumed.extend(inputs.iter().map(|t| self.resolve(t)));
+        let ret: Vec<ValueTypes> = ret.iter().map(|t| self.resolve(t)).collect();
+
+        self.type_error(&format!(
+            "Declared stack effect {}, but the body has {}",
+            stack_effect(&signature.inputs, &signature.outputs),
+            stack_effect(&consumed, &ret)
+        ))
+    }
+
+    // checks that a method that is not pure, an action, is not called inside any function scope
+    pub fn before_action(&self) -> Result<(), Diagnostic> {
         if self.defnstack.contains(&DefinitionTypes::Function) {
-            self.throw_error(&format!("Can not call actions from inside a function"));
+            return Err(self.error("Can not call actions from inside a function"));
         }
+        Ok(())
     }
 
     // does the variable exist in scope? the actual data location is handled by the backend
+    // index of the varscope the variable lives in
+    pub fn variable_scope(&self, vname: &str) -> Option<usize> {
+        self.varscopes
+            .iter()
+            .position(|scope| scope.contains_key(vname))
+    }
+
     pub fn variable_in_scope(&self, vname: &str) -> Option<ValueTypes> {
         for scope in self.varscopes.iter() {
             if scope.contains_key(vname) {
@@ -208,10 +562,6 @@ impl CompileState {
         self.varscopes.push(HashMap::new())
     }
 
-    pub fn grow_metastack(&mut self) {
-        self.metastack.push(vec![])
-    }
-
     pub fn grow_typestack(&mut self) {
         self.typestack.push(vec![])
     }
@@ -220,17 +570,49 @@ impl CompileState {
         self.voidstack.push(vec![])
     }
 
-    pub fn throw_error(&self, msg: &str) -> ! {
-        let line = self.token_line;
-        let token = self.tokenumber;
-        let file = &self.token_file;
-        panic!("[DRYFT ERROR] {file}:{line}, word {token}: {msg}")
+    pub fn depth(&self) -> Depth {
+        Depth {
+            defnstack: self.defnstack.len(),
+            bodystack: self.bodystack.len(),
+            varscopes: self.varscopes.len(),
+            typestack: self.typestack.len(),
+            voidstack: self.voidstack.len(),
+        }
+    }
+
+    // drop everything a broken definition left behind
+    pub fn restore(&mut self, depth: Depth) {
+        self.defnstack.truncate(depth.defnstack);
+        self.bodystack.truncate(depth.bodystack);
+        self.varscopes.truncate(depth.varscopes);
+        self.typestack.truncate(depth.typestack);
+        self.voidstack.truncate(depth.voidstack);
+    }
+
+    // point diagnostics at this token
+    pub fn locate(&mut self, token: &Token) {
+        self.token_span = token.span.clone();
+        self.tokenumber = token.word;
+    }
+
+    // describe a problem with the current token
+    pub fn diagnostic(&self, severity: Severity, msg: &str) -> Diagnostic {
+        let src = self
+            .sources
+            .get(&self.token_span.file)
+            .map(String::as_str)
+            .unwrap_or("");
+        Diagnostic::new(severity, self.token_span.clone(), self.tokenumber, msg, src)
+    }
+
+    // the caller is expected to abort the current token with it
+    pub fn error(&self, msg: &str) -> Diagnostic {
+        self.diagnostic(Severity::Error, msg)
     }
 
-    pub fn throw_warning(&self, msg: &str) {
-        let line = self.token_line;
-        let token = self.tokenumber;
-        let file = &self.token_file;
-        println!("[DRYFT WARNING] {file}:{line}, word {token}: {msg}")
+    // warnings do not stop compilation, so they are collected right away
+    pub fn warning(&mut self, msg: &str) {
+        let d = self.diagnostic(Severity::Warning, msg);
+        self.diagnostics.push(d);
     }
 }
@@ -0,0 +1,401 @@
+/*
+* Copyright (C) 2025 Filip Chovanec
+*
+* This program is free software: you can redistribute it and/or modify
+* it under the terms of the GNU General Public License as published by
+* the Free Software Foundation, either version 3 of the License, or
+* (at your option) any later version.
+*
+* This program is distributed in the hope that it will be useful,
+* but WITHOUT ANY WARRANTY; without even the implied warranty of
+* MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+* GNU General Public License for more details.
+*
+* You should have received a copy of the GNU General Public License
+* along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+// target descriptors say which backend to use and how to turn its output into something runnable
+// the ones in src/targets/ are built into dryftc, users and projects can add their own or replace them
+
+use crate::backends;
+use regex::Regex;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+const BUILTIN: &[(&str, &str)] = &[
+    ("elf", include_str!("targets/elf.toml")),
+    ("gcc", include_str!("targets/gcc.toml")),
+    ("llvm", include_str!("targets/llvm.toml")),
+    ("vm", include_str!("targets/vm.toml")),
+    ("wasm", include_str!("targets/wasm.toml")),
+];
+
+// the native sources dryft programs link against, written into the build directory so dryftc
+// does not depend on where it was built
+const STDLIB: &[(&str, &str)] = &[
+    ("stdc/std.c", include_str!("../native/stdc/std.c")),
+    ("wasm/run.mjs", include_str!("../native/wasm/run.mjs")),
+];
+
+#[derive(Debug, Deserialize)]
+pub struct TargetDesc {
+    // only provides linux support for now, windows/mac support in the future
+    pub unix: Option<TargetSpec>,
+    pub windows: Option<TargetSpec>,
+}
+
+// platform independent
+// paths and commands may use {builddir} {intermediate} {object} {output} {stdlib_dir} and {stdlib_object}, see BuildPaths
+// native also gets the {source} it compiles and the {native_object} to put it in
+#[derive(Debug, Deserialize)]
+pub struct TargetSpec {
+    #[serde(default)]
+    pub dependencies: Vec<String>, // tools the commands below need, like "gcc" or "nasm >= 2.14"
+    pub backend: String,
+    pub intermediate: String,     // file to write dryftc output to
+    pub output: Option<String>,   // what link produces, a.out in the working directory by default
+    pub assemble: Option<String>, // command describing how to use an external compiler to finalize compilation.
+    pub link: Option<String>,
+    pub native: Option<String>, // compiles a C source of the project to link in, targets without it can not
+    pub interpret: Option<String>, // command to run the final product. If none, use default system execute function (TODO)
+    pub stdlib: Option<String>,
+}
+
+impl TargetDesc {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let desc: Self = toml::from_str(text).map_err(|e| e.to_string())?;
+        for spec in [&desc.unix, &desc.windows].into_iter().flatten() {
+            if !backends::NAMES.contains(&spec.backend.as_str()) {
+                return Err(format!(
+                    "unknown backend {}, expected one of {}",
+                    spec.backend,
+                    backends::NAMES.join(", ")
+                ));
+            }
+        }
+        Ok(desc)
+    }
+
+    // the part of the description for the OS we are running on
+    pub fn host(&self) -> Option<&TargetSpec> {
+        match env::consts::FAMILY {
+            "unix" => self.unix.as_ref(),
+            "windows" => self.windows.as_ref(),
+            _ => None,
+        }
+    }
+
+    pub fn into_host(self) -> Option<TargetSpec> {
+        match env::consts::FAMILY {
+            "unix" => self.unix,
+            "windows" => self.windows,
+            _ => None,
+        }
+    }
+}
+
+impl TargetSpec {
+    // everything wrong with the tools the commands about to run need, empty if they are good to go
+    // a dependency belongs to the commands that call it, so skipped steps need nothing
+    pub fn check_dependencies(&self, commands: &[&str]) -> Vec<String> {
+        let mut problems = vec![];
+        for dep in self.dependencies.iter() {
+            let dep = match Dependency::parse(dep) {
+                Ok(dep) => dep,
+                Err(e) => {
+                    problems.push(e);
+                    continue;
+                }
+            };
+            if !commands.iter().any(|cmd| calls(cmd, &dep.tool)) {
+                continue;
+            }
+            let Some(path) = find_on_path(&dep.tool) else {
+                problems.push(format!("{} is not on PATH", dep.tool));
+                continue;
+            };
+            let Some(min) = &dep.min_version else {
+                continue;
+            };
+            match tool_version(&path) {
+                Some(v) if at_least(&v, min) => {}
+                Some(v) => problems.push(format!(
+                    "{} {} is too old, at least {} is needed",
+                    dep.tool,
+                    show_version(&v),
+                    show_version(min)
+                )),
+                None => problems.push(format!(
+                    "could not tell which version of {} is installed, at least {} is needed",
+                    dep.tool,
+                    show_version(min)
+                )),
+            }
+        }
+        problems
+    }
+}
+
+// a tool as written in dependencies, optionally with a minimum version
+#[derive(Debug, PartialEq)]
+pub struct Dependency {
+    pub tool: String,
+    pub min_version: Option<Vec<u32>>,
+}
+
+impl Dependency {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let (tool, min_version) = match text.split_once(">=") {
+            Some((tool, version)) => {
+                let version = parse_version(version.trim())
+                    .ok_or_else(|| format!("bad version in dependency \"{text}\""))?;
+                (tool.trim(), Some(version))
+            }
+            None => (text.trim(), None),
+        };
+        if tool.is_empty() || tool.contains(char::is_whitespace) {
+            return Err(format!("bad dependency \"{text}\""));
+        }
+        Ok(Self {
+            tool: tool.to_string(),
+            min_version,
+        })
+    }
+}
+
+// tool is one of the words of cmd, by name or by path
+fn calls(cmd: &str, tool: &str) -> bool {
+    cmd.split_whitespace()
+        .any(|word| Path::new(word).file_name().is_some_and(|name| name == tool))
+}
+
+fn parse_version(text: &str) -> Option<Vec<u32>> {
+    text.split('.').map(|n| n.parse().ok()).collect()
+}
+
+fn show_version(v: &[u32]) -> String {
+    v.iter().map(u32::to_string).collect::<Vec<_>>().join(".")
+}
+
+// missing parts count as 0, so 2.14 is at least 2.14.0
+pub fn at_least(version: &[u32], min: &[u32]) -> bool {
+    let len = version.len().max(min.len());
+    let pad = |v: &[u32]| {
+        (0..len)
+            .map(|i| v.get(i).copied().unwrap_or(0))
+            .collect::<Vec<_>>()
+    };
+    pad(version) >= pad(min)
+}
+
+// the first thing that looks like a version in what the tool says about itself
+pub fn version_in(text: &str) -> Option<Vec<u32>> {
+    let re = Regex::new(r"\d+(\.\d+)+").unwrap();
+    parse_version(re.find(text)?.as_str())
+}
+
+fn tool_version(path: &Path) -> Option<Vec<u32>> {
+    let output = Command::new(path).arg("--version").output().ok()?;
+    version_in(&String::from_utf8_lossy(&output.stdout))
+        .or_else(|| version_in(&String::from_utf8_lossy(&output.stderr)))
+}
+
+pub fn find_on_path(tool: &str) -> Option<PathBuf> {
+    let names = if cfg!(windows) {
+        vec![tool.to_string(), format!("{tool}.exe")]
+    } else {
+        vec![tool.to_string()]
+    };
+    env::split_paths(&env::var_os("PATH")?)
+        .flat_map(|dir| names.iter().map(move |n| dir.join(n)))
+        .find(|path| path.is_file())
+}
+
+// where one build puts its files, giving each build its own builddir lets them run side by side
+#[derive(Debug, Clone, PartialEq)]
+pub struct BuildPaths {
+    pub builddir: PathBuf,
+    pub intermediate: PathBuf,
+    pub object: PathBuf, // what assemble makes of the intermediate
+    pub output: PathBuf,
+    pub stdlib_dir: PathBuf, // the native sources dryft programs link against
+    pub stdlib_object: PathBuf, // what stdlib compiles them to
+}
+
+impl BuildPaths {
+    // anything left as None comes from the target
+    pub fn new(
+        spec: &TargetSpec,
+        builddir: PathBuf,
+        intermediate: Option<PathBuf>,
+        output: Option<PathBuf>,
+    ) -> Self {
+        let in_builddir = |template: &str| {
+            PathBuf::from(template.replace("{builddir}", &builddir.display().to_string()))
+        };
+        let intermediate = intermediate.unwrap_or_else(|| in_builddir(&spec.intermediate));
+        let stem = intermediate
+            .file_stem()
+            .unwrap_or_default()
+            .to_string_lossy();
+        let mut output =
+            output.unwrap_or_else(|| in_builddir(spec.output.as_deref().unwrap_or("a.out")));
+        // so it can be run as a command
+        if output.parent() == Some(Path::new("")) {
+            output = Path::new(".").join(output);
+        }
+        Self {
+            object: builddir.join(format!("{stem}.o")),
+            stdlib_object: builddir.join("stdc.o"),
+            stdlib_dir: builddir.join("stdlib"),
+            builddir,
+            intermediate,
+            output,
+        }
+    }
+
+    // fill in the placeholders of a target command, every path is quoted for bash
+    pub fn expand(&self, command: &str) -> String {
+        [
+            ("{builddir}", &self.builddir),
+            ("{intermediate}", &self.intermediate),
+            ("{object}", &self.object),
+            ("{output}", &self.output),
+            ("{stdlib_dir}", &self.stdlib_dir),
+            ("{stdlib_object}", &self.stdlib_object),
+        ]
+        .iter()
+        .fold(command.to_string(), |cmd, (placeholder, path)| {
+            cmd.replace(placeholder, &quote(path))
+        })
+    }
+
+    pub fn stdlib_files(&self) -> Vec<PathBuf> {
+        STDLIB
+            .iter()
+            .map(|(name, _)| self.stdlib_dir.join(name))
+            .collect()
+    }
+
+    // puts the embedded stdlib where {stdlib_dir} points
+    pub fn write_stdlib(&self) -> io::Result<()> {
+        for (path, (_, text)) in self.stdlib_files().iter().zip(STDLIB) {
+            fs::create_dir_all(path.parent().unwrap_or(Path::new("")))?;
+            fs::write(path, text)?;
+        }
+        Ok(())
+    }
+
+    // numbered, so native sources with the same file name do not overwrite each others objects
+    pub fn native_object(&self, n: usize, source: &Path) -> PathBuf {
+        let stem = source.file_stem().unwrap_or_default().to_string_lossy();
+        self.builddir.join(format!("native{n}-{stem}.o"))
+    }
+
+    // the native command of a target for the nth native source
+    pub fn expand_native(&self, command: &str, n: usize, source: &Path) -> String {
+        self.expand(command)
+            .replace("{source}", &quote(source))
+            .replace("{native_object}", &quote(&self.native_object(n, source)))
+    }
+}
+
+pub fn quote(path: &Path) -> String {
+    format!("'{}'", path.display().to_string().replace('\'', r"'\''"))
+}
+
+// where a target description was found
+#[derive(Debug, Clone, PartialEq)]
+pub enum Origin {
+    Builtin,
+    File(PathBuf),
+}
+
+impl fmt::Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Origin::Builtin => write!(f, "built in"),
+            Origin::File(path) => write!(f, "{}", path.display()),
+        }
+    }
+}
+
+// user targets live in the config directory, e.g. ~/.config/dryft/targets/
+pub fn user_dir() -> Option<PathBuf> {
+    let config = if cfg!(windows) {
+        env::var_os("APPDATA").map(PathBuf::from)
+    } else {
+        env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| env::home_dir().map(|home| home.join(".config")))
+    };
+    config.map(|c| c.join("dryft").join("targets"))
+}
+
+// every known target by name, a later directory replaces targets of the same name from an earlier one
+pub struct Targets {
+    found: BTreeMap<String, (Origin, String)>,
+}
+
+impl Targets {
+    pub fn builtin() -> Self {
+        let found = BUILTIN
+            .iter()
+            .map(|(name, text)| (name.to_string(), (Origin::Builtin, text.to_string())))
+            .collect();
+        Self { found }
+    }
+
+    // builtin targets, then the user's, then those of the project
+    pub fn discover(project: Option<&Path>) -> Self {
+        let mut targets = Self::builtin();
+        if let Some(dir) = user_dir() {
+            targets.add_dir(&dir);
+        }
+        if let Some(root) = project {
+            targets.add_dir(&root.join("targets"));
+        }
+        targets
+    }
+
+    // every name.toml in dir, missing directories are fine
+    pub fn add_dir(&mut self, dir: &Path) {
+        let Ok(entries) = fs::read_dir(dir) else {
+            return;
+        };
+        for path in entries.flatten().map(|e| e.path()) {
+            if path.extension().is_none_or(|e| e != "toml") {
+                continue;
+            }
+            let (Some(name), Ok(text)) = (path.file_stem(), fs::read_to_string(&path)) else {
+                continue;
+            };
+            let name = name.to_string_lossy().to_string();
+            self.found.insert(name, (Origin::File(path), text));
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Result<TargetDesc, String> {
+        let Some((origin, text)) = self.found.get(name) else {
+            return Err(format!(
+                "Unknown target {name}, --list-targets shows the ones available"
+            ));
+        };
+        TargetDesc::parse(text).map_err(|e| format!("Invalid target description {origin}: {e}"))
+    }
+
+    // name, where it came from and its description, sorted by name
+    pub fn list(&self) -> Vec<(&str, &Origin, Result<TargetDesc, String>)> {
+        self.found
+            .iter()
+            .map(|(name, (origin, text))| (name.as_str(), origin, TargetDesc::parse(text)))
+            .collect()
+    }
+}
@@ -24,18 +24,11 @@ fn make_strings(v: Vec<&str>) -> Vec<String> {
     v.into_iter().map(String::from).collect()
 }
 
-// handle specific result for CompilerState::throw_error()
-#[cfg(feature = "typesystem")]
+// compare the first diagnostic produced by a failed compilation
 fn expect_dryft_err(code: &str, e: &str) {
-    use std::panic;
-    let result = panic::catch_unwind(|| {
-        let mut backend: Box<dyn Backend> = Box::new(MockBackend {});
-        compile(&mut backend, code);
-    });
-    assert_eq!(
-        e.to_string(),
-        *result.unwrap_err().downcast_ref::<String>().unwrap()
-    );
+    let mut backend: Box<dyn Backend> = Box::new(MockBackend {});
+    let diagnostics = compile(&mut backend, code).unwrap_err();
+    assert_eq!(e.to_string(), diagnostics[0].to_string());
 }
 
 #[test]
@@ -48,7 +41,8 @@ fn ts_internals() {
     cs.grow_typestack();
     cs.push_type(ValueTypes::Number);
     cs.push_type(ValueTypes::Text);
-    cs.expect_types(&[ValueTypes::Text, ValueTypes::Number]);
+    cs.expect_types(&[ValueTypes::Text, ValueTypes::Number])
+        .unwrap();
 }
 
 // TODO: impl this to check if return order is correct in TS
@@ -64,7 +58,7 @@ fn ts_function_multi_return() {
             foo 1 +
         :act
         ",
-        "[DRYFT ERROR] <main>:6, word 1: Type mismatch : Expected Number, found Binary",
+        "[DRYFT ERROR] <main>:6, word 3: Type mismatch : Expected Number, found Binary",
     );
 }
 
@@ -132,7 +126,7 @@ fn ts_function_inference() {
 #[test]
 fn simple_parse() {
     let mut backend: Box<dyn Backend> = Box::new(MockBackend {});
-    let mut cs = compile(&mut backend, "fun: inc\n\t1 + :fun");
+    let cs = compile(&mut backend, "fun: inc\n\t1 + :fun").unwrap();
     assert_eq!(
         cs.log_tokens,
         make_strings(vec!["fun:", "inc", "1", "+", ":fun"])
@@ -142,16 +136,16 @@ fn simple_parse() {
 #[test]
 fn semicolon_ending() {
     let mut backend: Box<dyn Backend> = Box::new(MockBackend {});
-    let cs = compile(&mut backend, "fun: id ;");
+    let cs = compile(&mut backend, "fun: id ;").unwrap();
     let mut backend: Box<dyn Backend> = Box::new(MockBackend {});
-    let cs2 = compile(&mut backend, "fun: id :fun");
+    let cs2 = compile(&mut backend, "fun: id :fun").unwrap();
     assert_eq!(cs.out, cs2.out);
 }
 
 #[test]
 fn function_compilation() {
     let mut backend: Box<dyn Backend> = Box::new(C99Backend {});
-    let cs = compile(&mut backend, "fun: sum3 + + :fun");
+    let cs = compile(&mut backend, "fun: sum3 + + :fun").unwrap();
 
     assert_eq!(
         cs.out.unwrap(),
@@ -162,5 +156,968 @@ fn function_compilation() {
 #[test]
 fn strings() {
     let mut backend: Box<dyn Backend> = Box::new(C99Backend {});
-    let cs = compile(&mut backend, "fun idk \" # fake comment # \" ; ");
+    assert!(compile(&mut backend, "fun idk \" # fake comment # \" ; ").is_ok());
+}
+
+#[test]
+fn unknown_token() {
+    expect_dryft_err(
+        "act: main \n\t1 frobnicate ;",
+        "[DRYFT ERROR] <main>:2, word 2: Unknown token 'frobnicate'",
+    );
+}
+
+#[test]
+fn diagnostic_position() {
+    let mut backend: Box<dyn Backend> = Box::new(MockBackend {});
+    let diagnostics = compile(&mut backend, "act: main\n  1 2 ;\n;").unwrap_err();
+    assert_eq!(diagnostics.len(), 1);
+    let span = &diagnostics[0].span;
+    assert_eq!((span.start_line, span.start_column), (3, 1));
+    assert_eq!((span.start, span.end), (18, 19));
+    assert!(diagnostics[0].is_error());
+}
+
+#[test]
+fn error_recovery() {
+    let mut backend: Box<dyn Backend> = Box::new(MockBackend {});
+    let diagnostics = compile(
+        &mut backend,
+        "fun: broken
+            1 nope then: 2 ; +
+        :fun
+        ;
+        act: main foo 1 bar ;
+        act: fine 1 2 + ;",
+    )
+    .unwrap_err();
+    let messages: Vec<String> = diagnostics.iter().map(|d| d.message.clone()).collect();
+    assert_eq!(
+        messages,
+        make_strings(vec![
+            "Unknown token 'nope'",
+            " - Misplaced ;",
+            "Unknown token 'foo'"
+        ])
+    );
+
+    // using a broken definition only reports the definition
+    let mut backend: Box<dyn Backend> = Box::new(MockBackend {});
+    let diagnostics = compile(&mut backend, "fun: bad nope ; act: main 1 bad ;").unwrap_err();
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0].message, "Unknown token 'nope'");
+}
+
+#[test]
+fn break_outside_cycle() {
+    expect_dryft_err(
+        "act: main break ;",
+        "[DRYFT ERROR] <main>:1, word 3: Can not break outside of a cycle",
+    );
+    expect_dryft_err(
+        "act: main true then: break ; ;",
+        "[DRYFT ERROR] <main>:1, word 5: Can not break outside of a cycle",
+    );
+}
+
+#[test]
+fn diagnostic_render() {
+    let mut backend: Box<dyn Backend> = Box::new(MockBackend {});
+    let diagnostics = compile(&mut backend, "act: main\n\t1 frob ;").unwrap_err();
+    assert_eq!(
+        diagnostics[0].render(),
+        "error: Unknown token 'frob'\n --> <main>:2:4\n  |\n2 | \t1 frob ;\n  | \t  ^^^^\n"
+    );
+}
+
+#[test]
+fn lexer_kinds() {
+    use crate::lexer::{lex, TokenKind};
+
+    let tokens = lex("<main>", "act: main # hi # \"a b\" (Int ->) -12 prints ;").unwrap();
+    let kinds: Vec<TokenKind> = tokens.iter().map(|t| t.kind).collect();
+    assert_eq!(
+        kinds,
+        vec![
+            TokenKind::Keyword,
+            TokenKind::Word,
+            TokenKind::Comment,
+            TokenKind::String,
+            TokenKind::Annotation,
+            TokenKind::Integer,
+            TokenKind::Word,
+            TokenKind::Keyword,
+        ]
+    );
+    assert_eq!(tokens[3].text, "a b");
+    assert_eq!((tokens[3].span.start, tokens[3].span.end), (17, 22));
+    assert_eq!((tokens[6].span.start_column, tokens[6].word), (37, 4));
+
+    // a delimited token ends where it is closed, even lines later
+    let tokens = lex("<main>", "1 # two\nlines # x").unwrap();
+    let span = &tokens[1].span;
+    assert_eq!((span.start_line, span.start_column), (1, 3));
+    assert_eq!((span.end_line, span.end_column, span.end), (2, 7, 15));
+    assert_eq!((tokens[2].span.start_line, tokens[2].word), (2, 1));
+}
+
+#[test]
+fn lexer_unterminated() {
+    let mut backend: Box<dyn Backend> = Box::new(MockBackend {});
+    let diagnostics = compile(&mut backend, "act: main\n  \"oops ;").unwrap_err();
+    assert_eq!(
+        diagnostics[0].message,
+        "Unterminated string, expected a closing \""
+    );
+    assert_eq!(diagnostics[0].span.start_line, 2);
+}
+
+#[test]
+fn ast_shape() {
+    use crate::ast::{Block, Definition, Literal, NodeKind};
+    use crate::parser::Parser;
+
+    let mut parser = Parser::new(
+        "<main>",
+        "linkin act _x_print_int
+        act: main 1 var: x cycle: $x 10 >? then: break ; $x 1 + x! ; :act",
+    )
+    .unwrap();
+    let program = parser.program();
+    assert!(parser.diagnostics.is_empty());
+    assert_eq!(program.definitions.len(), 2);
+
+    let Definition::Act(main) = &program.definitions[1] else {
+        panic!("expected an action");
+    };
+    assert_eq!(main.name, "main");
+    assert_eq!(main.body.end.text, ":act");
+    assert_eq!(
+        main.body.nodes[0].kind,
+        NodeKind::Literal(Literal::Integer("1".into()))
+    );
+    assert_eq!(main.body.nodes[1].kind, NodeKind::VarDef("x".into()));
+    let NodeKind::Block(Block::Cycle(cycle)) = &main.body.nodes[2].kind else {
+        panic!("expected a cycle");
+    };
+    assert_eq!(cycle.nodes[0].kind, NodeKind::VarRead("x".into()));
+    assert!(matches!(
+        cycle.nodes[3].kind,
+        NodeKind::Block(Block::Then(_))
+    ));
+    assert_eq!(cycle.nodes[7].kind, NodeKind::VarWrite("x".into()));
+}
+
+#[test]
+fn nested_definition() {
+    expect_dryft_err(
+        "act: main fun: inner ; ;",
+        "[DRYFT ERROR] <main>:1, word 3: Definitions can not be nested",
+    );
+}
+
+#[test]
+#[cfg(feature = "typesystem")]
+fn ts_annotation_inputs() {
+    use crate::state::ValueTypes;
+
+    let mut backend: Box<dyn Backend> = Box::new(MockBackend {});
+    let cs = compile(
+        &mut backend,
+        "(Int Int -> Bool)
+        fun: divby mod 0 =? ;
+
+        (Int ->)
+        act: check var: n $n 3 divby then: ; ;",
+    )
+    .unwrap();
+    let divby = cs.methods.get("divby").unwrap();
+    assert_eq!(divby.itypes, vec![ValueTypes::Number, ValueTypes::Number]);
+    assert_eq!(divby.etypes, vec![ValueTypes::Binary]);
+}
+
+#[test]
+#[cfg(feature = "typesystem")]
+fn ts_annotation_mismatch() {
+    expect_dryft_err(
+        "(Int -> Bool)
+        fun: bad 1 + ;",
+        "[DRYFT ERROR] <main>:2, word 2: Declared stack effect (Number -> Binary), but the body has (Number -> Number)",
+    );
+}
+
+#[test]
+fn annotation_syntax() {
+    expect_dryft_err(
+        "(Int Cake -> Bool) fun: bad ;",
+        "[DRYFT ERROR] <main>:1, word 0: Unknown type Cake in annotation",
+    );
+    expect_dryft_err(
+        "fun: sq (Int -> Int) copy * ;",
+        "[DRYFT ERROR] <main>:1, word 0: Annotations can only be placed before fun: or act:",
+    );
+    expect_dryft_err(
+        "act: main 1 then: (Int ->) drop ; ;",
+        "[DRYFT ERROR] <main>:1, word 0: Annotations can only be placed before fun: or act:",
+    );
+    expect_dryft_err(
+        "act: main ; (Int -> Int)",
+        "[DRYFT ERROR] <main>:1, word 0: Annotations can only be placed before fun: or act:",
+    );
+}
+
+#[test]
+#[cfg(feature = "typesystem")]
+fn ts_linkin_signature() {
+    expect_dryft_err(
+        "linkin act _x_print_int (Int ->)
+        act: main \"text\" _x_print_int ;",
+        "[DRYFT ERROR] <main>:2, word 3: Type mismatch : Expected Number, found Text",
+    );
+}
+
+#[test]
+fn linkin_signature() {
+    use crate::state::ValueTypes;
+
+    let mut backend: Box<dyn Backend> = Box::new(MockBackend {});
+    let cs = compile(&mut backend, "linkin fun _x_add3 (Int Int Text -> Int)").unwrap();
+    let linked = cs.methods.get("_x_add3").unwrap();
+    assert_eq!(
+        linked.itypes,
+        vec![ValueTypes::Text, ValueTypes::Number, ValueTypes::Number]
+    );
+    assert_eq!(linked.etypes, vec![ValueTypes::Number]);
+}
+
+#[test]
+fn typecheck_modes() {
+    use crate::frontend::compile_with;
+    use crate::state::{CompileOptions, TypeCheck};
+
+    let compile_as = |code: &str, typecheck| {
+        let mut backend: Box<dyn Backend> = Box::new(MockBackend {});
+        compile_with(
+            &mut backend,
+            code,
+            CompileOptions {
+                typecheck,
+                ..Default::default()
+            },
+        )
+    };
+
+    let with = |typecheck| compile_as("act: main \"text\" 1 + ;", typecheck);
+
+    assert!(with(TypeCheck::Off).unwrap().diagnostics.is_empty());
+    // linear checks are type checks too
+    let leak = "linkin fun _open (-> Linear<Text>) act: main _open var h _open ;";
+    assert!(compile_as(leak, TypeCheck::Off)
+        .unwrap()
+        .diagnostics
+        .is_empty());
+
+    let warned = with(TypeCheck::Warn).unwrap();
+    assert_eq!(warned.diagnostics.len(), 1);
+    assert!(!warned.diagnostics[0].is_error());
+
+    let failed = with(TypeCheck::Strict).unwrap_err();
+    assert_eq!(
+        failed[0].message,
+        "Type mismatch : Expected Number, found Text"
+    );
+}
+
+#[test]
+#[cfg(feature = "typesystem")]
+fn ts_polymorphic_words() {
+    let mut backend: Box<dyn Backend> = Box::new(MockBackend {});
+    compile(
+        &mut backend,
+        "linkin act _x_print_string (Text ->)
+        (a b -> b a) fun: flip swap ;
+        act: main 1 \"x\" flip drop copy _x_print_string _x_print_string ;",
+    )
+    .unwrap();
+
+    expect_dryft_err(
+        "(a b -> b a) fun: flip swap ;
+        act: main \"x\" 1 flip 1 + ;",
+        "[DRYFT ERROR] <main>:2, word 6: Type mismatch : Expected Number, found Text",
+    );
+    expect_dryft_err(
+        "(a -> a) fun: bad 1 + ;",
+        "[DRYFT ERROR] <main>:1, word 2: Declared stack effect (a -> a), but the body has (Number -> Number)",
+    );
+}
+
+#[test]
+#[cfg(feature = "typesystem")]
+fn ts_text_equality() {
+    expect_dryft_err(
+        "act: main \"a\" \"b\" =? drop ;",
+        "[DRYFT ERROR] <main>:1, word 3: Can not compare Text using =?, it would only compare where the strings are stored",
+    );
+    expect_dryft_err(
+        "act: main 1 true !=? drop ;",
+        "[DRYFT ERROR] <main>:1, word 5: Can not compare Number with Binary using !=?",
+    );
+}
+
+#[test]
+#[cfg(feature = "typesystem")]
+fn ts_linear() {
+    let prelude = "linkin fun _open (-> Linear<Text>)
+        linkin act _close (Linear<Text> ->)
+        ";
+    let linear_err = |code: &str, e: &str| {
+        let mut backend: Box<dyn Backend> = Box::new(MockBackend {});
+        let diagnostics = compile(&mut backend, &format!("{prelude}{code}")).unwrap_err();
+        assert_eq!(e, diagnostics[0].message);
+    };
+
+    let mut backend: Box<dyn Backend> = Box::new(MockBackend {});
+    compile(
+        &mut backend,
+        &format!("{prelude}act: main _open var h $h _close 1 _open swap drop _close ;"),
+    )
+    .unwrap();
+
+    linear_err(
+        "act: main _open drop ;",
+        "Can not drop a linear value of type Linear<Text>, it has to be consumed exactly once",
+    );
+    linear_err(
+        "act: main _open copy _close _close ;",
+        "Can not copy a linear value of type Linear<Text>, it has to be consumed exactly once",
+    );
+    linear_err(
+        "act: main _open var h $h _close $h _close ;",
+        "Linear variable h was already used",
+    );
+    linear_err(
+        "act: main _open var h _open h! $h _close ;",
+        "Writing to h would drop the linear value it still holds",
+    );
+    linear_err("act: main _open var h ;", "Linear variable h is never used");
+    linear_err(
+        "act: main _open var h cycle: $h _close ; ;",
+        "Linear variable h can not be read inside a cycle, it may run more than once",
+    );
+    linear_err(
+        "act: main _open var h true then: $h _close ; ;",
+        "Linear variable h has to be used on every branch or on none",
+    );
+    linear_err(
+        "act: main _open var h when: true then: $h _close ; ; ;",
+        "Linear variable h has to be used on every branch or on none",
+    );
+    linear_err(
+        "act: main _open var h return $h _close ;",
+        "Linear variable h is never used before return",
+    );
+    linear_err(
+        "act: main cycle: _open var g break $g _close ; ;",
+        "Linear variable g is never used before break",
+    );
+    // a then that returns does not have to agree with the code after it
+    let mut backend: Box<dyn Backend> = Box::new(MockBackend {});
+    compile(
+        &mut backend,
+        &format!("{prelude}act: main _open var h true then: $h _close return ; $h _close ;"),
+    )
+    .unwrap();
+    // one use on each way through an elect is exactly once
+    let mut backend: Box<dyn Backend> = Box::new(MockBackend {});
+    compile(
+        &mut backend,
+        &format!("{prelude}act: main _open var h when: true then: $h _close ; $h _close ; cycle: _open var g $g _close break ; ;"),
+    )
+    .unwrap();
+    linear_err(
+        "act: main _open ;",
+        "Linear value of type Linear<Text> left on the stack at the end of act main",
+    );
+    // generic words that copy can not be handed a linear value either
+    linear_err(
+        "fun: twice copy ; act: main _open twice _close _close ;",
+        "Type mismatch : Expected a value that can be copied or dropped, found Linear<Text>",
+    );
+}
+
+#[test]
+fn purity_chain() {
+    // tidy keeps calling the pure helper it was compiled against
+    let mut backend: Box<dyn Backend> = Box::new(MockBackend {});
+    compile(
+        &mut backend,
+        "fun: helper ;
+        fun: tidy helper ;
+        act: helper ;
+        fun: fine tidy ;",
+    )
+    .unwrap();
+    let mut backend: Box<dyn Backend> = Box::new(MockBackend {});
+    let diagnostics = compile(
+        &mut backend,
+        "fun: helper ;
+        act: helper ;
+        fun: bad helper ;",
+    )
+    .unwrap_err();
+    assert_eq!(
+        diagnostics[0].to_string(),
+        "[DRYFT ERROR] <main>:3, word 3: Can not call actions from inside a function"
+    );
+
+    // linked in functions are trusted
+    let mut backend: Box<dyn Backend> = Box::new(MockBackend {});
+    compile(
+        &mut backend,
+        "linkin fun _x_strlen
+        fun: measure _x_strlen ;
+        fun: main measure ;",
+    )
+    .unwrap();
+}
+
+#[test]
+fn x86_blocks() {
+    use crate::backends::x86::Nasm64Backend;
+
+    let mut backend: Box<dyn Backend> = Box::new(Nasm64Backend::default());
+    let cs = compile(
+        &mut backend,
+        "act: count 0 var: i
+            cycle: $i 3 =? then: break ; $i 1 + i! :cycle
+            when: true then: return ; \"a\\n\" ;
+        ;",
+    )
+    .unwrap();
+
+    let out = cs.out.unwrap();
+    assert!(out.starts_with(
+        "%define var_i rbp - 8\nfun_count:\n\tpush rbp\n\tmov rbp, rsp\n\tsub rsp, 16\n"
+    ));
+    assert!(out.contains(": db 97, 10, 0\n"));
+    assert!(!out.contains('{'), "unresolved jump in {out}");
+    assert!(out.ends_with("\tleave\n\tret\n%undef var_i\n\n"));
+}
+
+#[test]
+fn llvm_blocks() {
+    use crate::backends::llvm::LlvmBackend;
+
+    let mut backend: Box<dyn Backend> = Box::new(LlvmBackend::default());
+    let cs = compile(
+        &mut backend,
+        "act: count 0 var: i
+            cycle: $i 3 =? then: break ; $i 1 + i! :cycle
+            \"{a}\\n\" ;",
+    )
+    .unwrap();
+
+    let out = cs.out.unwrap();
+    // strings are hoisted before the function, allocas into the entry block
+    assert!(out.starts_with("@str."));
+    assert!(out.contains("c\"\\7Ba\\7D\\0A\\00\"\ndefine void @\"fun_count\"() {\nentry:\n  %\"var_i\" = alloca i64\n"));
+    assert!(!out.contains("{loop end}"));
+}
+
+#[test]
+fn wasm_module() {
+    use crate::backends::wasm::WatBackend;
+
+    let mut backend: Box<dyn Backend> = Box::new(WatBackend::default());
+    let cs = compile(
+        &mut backend,
+        "act: main 1 var: i \"hi\" _x_print_string ;
+        linkin act _x_print_string",
+    );
+    // linked in symbols have to exist before they are used
+    assert!(cs.is_err());
+
+    let mut backend: Box<dyn Backend> = Box::new(WatBackend::default());
+    let cs = compile(
+        &mut backend,
+        "linkin act _x_print_string
+        act: main 1 var: i \"hi\" _x_print_string ;",
+    )
+    .unwrap();
+    let module = backend.complete(&cs.out.unwrap());
+
+    // imports are moved in front of every definition
+    assert!(module.starts_with(
+        "(module\n(import \"env\" \"_x_print_string\" (func $_x_print_string))\n(memory (export \"memory\") 2)\n(global $sptr (mut i32) (i32.const 65536))\n;; Copyright"
+    ));
+    assert!(module
+        .contains("(data (i32.const 16) \"hi\\00\")\n(func $fun_main\n    (local $var_i i64)\n"));
+
+    // the data stack moves out of the way of string literals that do not fit the first page
+    let mut backend: Box<dyn Backend> = Box::new(WatBackend::default());
+    let long = "x".repeat(70000);
+    let cs = compile(&mut backend, &format!("act: main \"{long}\" drop ;")).unwrap();
+    let module = backend.complete(&cs.out.unwrap());
+    assert!(module
+        .contains("(memory (export \"memory\") 3)\n(global $sptr (mut i32) (i32.const 131072))"));
+}
+
+#[test]
+fn vm_run() {
+    use crate::backends::vm::VmBackend;
+    use crate::vm;
+
+    let run = |code: &str| {
+        let mut backend: Box<dyn Backend> = Box::new(VmBackend::default());
+        let cs = compile(&mut backend, code).unwrap();
+        let mut out = vec![];
+        vm::run(&backend.complete(&cs.out.unwrap()), &mut out)
+            .map(|_| String::from_utf8(out).unwrap())
+    };
+
+    assert_eq!(
+        run("linkin act _x_print_int (Int ->)
+            linkin act _x_print_string (Text ->)
+            fun: square copy * ;
+            act: main
+                0 var: i
+                cycle: $i 4 =? then: break ; $i square _x_print_int \" \" _x_print_string $i 1 + i! :cycle
+                when: false then: 1 _x_print_int ; 2 _x_print_int ;
+            ;"),
+        Ok("0 1 4 9 2".to_string())
+    );
+    assert_eq!(
+        run("act: main 1 0 / drop ;"),
+        Err(vm::VmError("Division by zero".to_string()))
+    );
+    assert_eq!(
+        run("linkin act park act: main park ;"),
+        Err(vm::VmError(
+            "line 3: Unknown native symbol park, the vm only provides _x_print_string and _x_print_int".to_string()
+        ))
+    );
+
+    // every call in the chain is live at once, one more than the vm allows
+    let deep = (1..=1000).fold("fun m0\nret\n".to_string(), |text, n| {
+        text + &format!("fun m{n}\ncall m{}\nret\n", n - 1)
+    });
+    let program = vm::assemble(&deep).unwrap();
+    assert_eq!(
+        vm::Machine::new().call(&program, "m1000", &mut vec![]),
+        Err(vm::VmError("Call stack overflow".to_string()))
+    );
+    assert_eq!(
+        vm::Machine::new().call(&program, "m998", &mut vec![]),
+        Ok(())
+    );
+}
+
+#[test]
+fn repl_session() {
+    use crate::repl::{ReplError, Session};
+    use crate::state::CompileOptions;
+
+    let mut session = Session::new(CompileOptions::default());
+    let mut eval = |line: &str| {
+        let mut out = vec![];
+        session
+            .eval(line, &mut out)
+            .map(|_| String::from_utf8(out).unwrap())
+    };
+
+    eval("linkin act _x_print_int (Int ->)").unwrap();
+    assert_eq!(eval("fun: sq copy * ; 3 4").unwrap(), "");
+    assert_eq!(eval("sq _x_print_int").unwrap(), "16");
+    assert_eq!(eval("_x_print_int").unwrap(), "3");
+
+    // variables and definitions outlive the line that made them
+    eval("10 var: x").unwrap();
+    assert_eq!(eval("$x sq x! $x _x_print_int").unwrap(), "100");
+
+    // a broken line is forgotten entirely
+    assert!(matches!(
+        eval("fun: cube copy sq * ; nope"),
+        Err(ReplError::Compile(_))
+    ));
+    assert!(matches!(eval("2 cube"), Err(ReplError::Compile(_))));
+
+    // an annotation has to be on the line of its definition
+    let Err(ReplError::Compile(d)) = eval("1 (Int -> Int)") else {
+        panic!("expected a compile error");
+    };
+    assert_eq!(
+        d[0].message,
+        "Annotations can only be placed before fun: or act:"
+    );
+
+    // functions still can not touch what the REPL owns
+    let Err(ReplError::Compile(d)) = eval("fun: reset 0 x! ;") else {
+        panic!("expected a compile error");
+    };
+    assert_eq!(
+        d[0].message,
+        "Can not write to variable x from inside a function, it outlives the call"
+    );
+
+    assert!(matches!(eval("1 0 /"), Err(ReplError::Runtime(_))));
+    assert!(session.machine.stack.is_empty());
+}
+
+#[test]
+fn repl_introspection() {
+    use crate::repl::Session;
+    use crate::state::{CompileOptions, TypeCheck};
+
+    let mut session = Session::new(CompileOptions {
+        typecheck: TypeCheck::Strict,
+        ..Default::default()
+    });
+    let mut out = vec![];
+    session
+        .eval("(Int -> Int) fun: sq copy * ; 3 \"hi\"", &mut out)
+        .unwrap();
+
+    assert_eq!(session.describe_stack(), "<2>\n3 : Number\n0 : Text\n");
+    assert_eq!(session.describe_words(), "fun sq (Number -> Number)\n");
+    let see = session.describe_word("sq").unwrap();
+    assert!(see.starts_with("# <repl>:1 #\nfun sq copy * ;\n"));
+    assert!(see.contains("mul"));
+    assert!(session.describe_word("cube").is_none());
+
+    session.reset();
+    assert_eq!(session.describe_stack(), "<0>\n");
+    assert_eq!(session.describe_words(), "");
+
+    // without typechecking only what was declared is shown
+    let mut session = Session::new(CompileOptions {
+        typecheck: TypeCheck::Off,
+        ..Default::default()
+    });
+    session
+        .eval(
+            "linkin act _x_print_int (Int ->) (Int -> Int) fun: sq copy * ; fun: cube copy sq * ;",
+            &mut out,
+        )
+        .unwrap();
+    assert_eq!(
+        session.describe_words(),
+        "linkin act _x_print_int (Number ->)\nfun cube\nfun sq (Number -> Number)\n"
+    );
+}
+
+#[test]
+fn repl_continuation() {
+    use crate::parser::unfinished;
+
+    assert!(unfinished("fun: sq\n"));
+    assert!(unfinished("act: main cycle: 1 ;\n"));
+    assert!(unfinished("\"half a\n"));
+    assert!(unfinished("# still talking\n"));
+    assert!(!unfinished("fun: sq copy * ;\n"));
+    assert!(!unfinished("fun: quadra double double :fun\n"));
+    assert!(!unfinished("1 2 + \"done\" # noted #\n"));
+    assert!(!unfinished("linkin act _x_print_int (Int ->)\n"));
+    assert!(unfinished("linkin fun twice act: main\n"));
+    // a stray closer is an error for the parser, not a reason to wait
+    assert!(!unfinished(";\n"));
+}
+
+#[test]
+fn project_manifest() {
+    use crate::manifest::Manifest;
+    use std::path::{Path, PathBuf};
+
+    let root = Path::new("/projects/demo");
+    let m = Manifest::parse(
+        "[project]\nname = \"demo\"\nentry = \"src/main.dry\"\ninclude = [\"lib\"]\nnative = [\"c/twice.c\"]\n",
+        root,
+    )
+    .unwrap();
+    assert_eq!(m.entry(), root.join("src/main.dry"));
+    assert_eq!(m.include_paths(), vec![root.join("lib")]);
+    assert_eq!(m.native_sources(), vec![root.join("c/twice.c")]);
+    assert_eq!(m.output(), root.join("demo"));
+    assert_eq!(m.project.target, None);
+
+    let m = Manifest::parse(
+        "[project]\nname = \"demo\"\nentry = \"main.dry\"\ntarget = \"vm\"\noutput = \"bin/demo\"\n",
+        root,
+    )
+    .unwrap();
+    assert_eq!(m.output(), PathBuf::from("/projects/demo/bin/demo"));
+    assert_eq!(m.project.target.as_deref(), Some("vm"));
+
+    // typos should not be silently ignored
+    assert!(Manifest::parse(
+        "[project]\nname = \"x\"\nentry = \"x.dry\"\nouput = \"y\"\n",
+        root
+    )
+    .is_err());
+    assert!(Manifest::parse("[project]\nname = \"x\"\n", root).is_err());
+}
+
+#[test]
+fn project_commands() {
+    use crate::{Cli, Project};
+    use clap::Parser;
+
+    // global flags work on either side of the subcommand
+    for args in [
+        ["dryftc", "-t", "vm", "build"],
+        ["dryftc", "build", "-t", "vm"],
+    ] {
+        let cli = Cli::try_parse_from(args).unwrap();
+        assert_eq!(cli.command, Some(Project::Build { dir: None }));
+        assert_eq!(cli.inputfile, None);
+        assert_eq!(cli.target.as_deref(), Some("vm"));
+    }
+    let cli = Cli::try_parse_from(["dryftc", "-t", "vm", "main.dry"]).unwrap();
+    assert_eq!(cli.command, None);
+}
+
+#[test]
+fn target_registry() {
+    use crate::targets::{Origin, TargetDesc, Targets};
+
+    // every built in target has to parse, and name a backend dryftc knows
+    let targets = Targets::builtin();
+    for (name, origin, desc) in targets.list() {
+        assert_eq!(*origin, Origin::Builtin);
+        let spec = desc
+            .unwrap()
+            .unix
+            .unwrap_or_else(|| panic!("{name} has no unix section"));
+        crate::backends::select(&spec.backend);
+    }
+    let gcc = targets.get("gcc").unwrap().unix.unwrap();
+    assert_eq!(gcc.backend, "C99");
+    assert_eq!(gcc.dependencies, vec!["gcc"]);
+
+    // a directory can add targets and replace built in ones
+    let dir = std::env::temp_dir().join(format!("dryft-targets-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let vm = "[unix]\nbackend = \"vm\"\nintermediate = \"build/ir.dvm\"\n";
+    std::fs::write(dir.join("gcc.toml"), vm).unwrap();
+    std::fs::write(dir.join("notes.txt"), "not a target").unwrap();
+    let mut targets = Targets::builtin();
+    targets.add_dir(&dir);
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(targets.get("gcc").unwrap().unix.unwrap().backend, "vm");
+    assert!(targets.get("notes").is_err());
+
+    // a backend dryftc does not know is caught before anything is built
+    let bad = "[unix]\nbackend = \"cobol\"\nintermediate = \"build/ir\"\n";
+    assert_eq!(
+        TargetDesc::parse(bad).unwrap_err(),
+        "unknown backend cobol, expected one of C99, x86, LLVM, wasm, vm"
+    );
+    assert_eq!(
+        targets.get("nope").unwrap_err(),
+        "Unknown target nope, --list-targets shows the ones available"
+    );
+}
+
+#[test]
+fn target_dependencies() {
+    use crate::targets::{at_least, find_on_path, version_in, Dependency, TargetDesc};
+
+    assert_eq!(
+        Dependency::parse("nasm >= 2.14").unwrap(),
+        Dependency {
+            tool: "nasm".to_string(),
+            min_version: Some(vec![2, 14]),
+        }
+    );
+    assert_eq!(Dependency::parse("gcc").unwrap().min_version, None);
+    assert!(Dependency::parse("gcc >= new").is_err());
+    assert!(Dependency::parse("two words").is_err());
+
+    assert_eq!(
+        version_in("gcc (Debian 12.2.0-14+deb12u1) 12.2.0"),
+        Some(vec![12, 2, 0])
+    );
+    assert_eq!(version_in("v20.20.2"), Some(vec![20, 20, 2]));
+    assert_eq!(version_in("no numbers here"), None);
+    assert!(at_least(&[2, 14], &[2, 14, 0]));
+    assert!(at_least(&[2, 16, 1], &[2, 14]));
+    assert!(!at_least(&[2, 9], &[2, 14]));
+
+    assert!(find_on_path("dryft-surely-not-installed").is_none());
+    let desc = TargetDesc::parse(
+        "[unix]\nbackend = \"vm\"\ndependencies = [\"dryft-surely-not-installed\"]\nintermediate = \"build/ir.dvm\"\n",
+    )
+    .unwrap();
+    let spec = desc.unix.unwrap();
+    assert_eq!(
+        spec.check_dependencies(&["/usr/bin/dryft-surely-not-installed -c {intermediate}"]),
+        vec!["dryft-surely-not-installed is not on PATH"]
+    );
+    // steps that do not run need nothing, like with --assembly-only
+    assert!(spec.check_dependencies(&[]).is_empty());
+    assert!(spec.check_dependencies(&["gcc -o {output}"]).is_empty());
+}
+
+#[test]
+fn target_placeholders() {
+    use crate::targets::{BuildPaths, Targets};
+    use std::path::PathBuf;
+
+    let gcc = Targets::builtin().get("gcc").unwrap().unix.unwrap();
+    let mut paths = BuildPaths::new(&gcc, PathBuf::from("build"), None, None);
+    assert_eq!(paths.intermediate, PathBuf::from("build/ir.c"));
+    assert_eq!(paths.object, PathBuf::from("build/ir.o"));
+    assert_eq!(paths.output, PathBuf::from("./a.out"));
+    assert_eq!(paths.stdlib_dir, PathBuf::from("build/stdlib"));
+    assert!(paths
+        .stdlib_files()
+        .contains(&PathBuf::from("build/stdlib/stdc/std.c")));
+
+    paths.stdlib_dir = PathBuf::from("/opt/dryft/native");
+    assert_eq!(
+        paths.expand(gcc.link.as_ref().unwrap()),
+        "gcc 'build/ir.o' 'build/stdc.o' -o './a.out'"
+    );
+    assert_eq!(
+        paths.expand(gcc.stdlib.as_ref().unwrap()),
+        "gcc '/opt/dryft/native'/stdc/std.c -c -fPIE -o 'build/stdc.o'"
+    );
+
+    // -a and -o win over the target, quotes in paths stay quoted
+    let paths = BuildPaths::new(
+        &gcc,
+        PathBuf::from("build/it's"),
+        Some(PathBuf::from("out/prog.c")),
+        Some(PathBuf::from("bin/prog")),
+    );
+    assert_eq!(paths.object, PathBuf::from("build/it's/prog.o"));
+    assert_eq!(
+        paths.expand("{object} {output}"),
+        r"'build/it'\''s/prog.o' 'bin/prog'"
+    );
+    // native sources of the same name still get their own objects
+    let native = gcc.native.as_ref().unwrap();
+    assert_eq!(
+        paths.expand_native(native, 0, &PathBuf::from("a/util.c")),
+        r"gcc -c -fPIE -o 'build/it'\''s/native0-util.o' 'a/util.c'"
+    );
+    assert_eq!(
+        paths.native_object(1, &PathBuf::from("b/util.c")),
+        PathBuf::from("build/it's/native1-util.o")
+    );
+
+    let wasm = Targets::builtin().get("wasm").unwrap().unix.unwrap();
+    let paths = BuildPaths::new(&wasm, PathBuf::from("build/web"), None, None);
+    assert_eq!(paths.output, PathBuf::from("build/web/out.wasm"));
+    assert_eq!(wasm.native, None);
+}
+
+#[test]
+fn include_search() {
+    use crate::parser::Parser;
+
+    let root = std::env::temp_dir().join(format!("dryft-includes-{}", std::process::id()));
+    let (src, lib) = (root.join("src"), root.join("lib"));
+    std::fs::create_dir_all(&src).unwrap();
+    std::fs::create_dir_all(&lib).unwrap();
+    std::fs::write(lib.join("util.dry"), "include: helpers\nfun: twice 2 * ;\n").unwrap();
+    std::fs::write(lib.join("helpers.dry"), "fun: inc 1 + ;\n").unwrap();
+    std::fs::write(src.join("local.dry"), "fun: dec 1 - ;\n").unwrap();
+
+    // next to the including file, then the search path, then the std/ built into dryftc
+    let main = src.join("main.dry");
+    let code = "include: local include: util include: std/io";
+    let mut parser = Parser::new(&main.display().to_string(), code).unwrap();
+    parser.include_paths = vec![lib.clone()];
+    let program = parser.program();
+    assert!(parser.diagnostics.is_empty(), "{:?}", parser.diagnostics);
+    assert_eq!(program.definitions.len(), 7);
+    assert!(parser
+        .sources
+        .contains_key(&src.join("local.dry").display().to_string()));
+    assert!(parser
+        .sources
+        .contains_key(&lib.join("helpers.dry").display().to_string()));
+    assert!(parser.sources.contains_key("<builtin>/std/io.dry"));
+
+    // each file is spliced in once, whichever way it is reached
+    std::fs::write(lib.join("spin.dry"), "include: spin\nfun: spin ;\n").unwrap();
+    let code = "include: util include: helpers include: spin include: main";
+    std::fs::write(&main, code).unwrap();
+    let mut parser = Parser::new(&main.display().to_string(), code).unwrap();
+    parser.include_paths = vec![lib.clone()];
+    let program = parser.program();
+    assert!(parser.diagnostics.is_empty(), "{:?}", parser.diagnostics);
+    assert_eq!(program.definitions.len(), 3);
+
+    let mut parser = Parser::new(&main.display().to_string(), "include: gone").unwrap();
+    parser.include_paths = vec![lib.clone()];
+    parser.program();
+    std::fs::remove_dir_all(&root).unwrap();
+
+    let e = &parser.diagnostics[0];
+    assert_eq!(e.message, "Could not locate include gone.dry");
+    assert_eq!(
+        e.notes,
+        vec![
+            format!("looked for {}", src.join("gone.dry").display()),
+            format!("looked for {}", lib.join("gone.dry").display()),
+            "and in the std/ built into dryftc".to_string(),
+        ]
+    );
+}
+
+#[test]
+fn repl_redefinition() {
+    use crate::repl::Session;
+    use crate::state::CompileOptions;
+
+    // a new inc builds on the old one instead of calling itself
+    let mut session = Session::new(CompileOptions::default());
+    let mut out = vec![];
+    session.eval("linkin act _x_print_int", &mut out).unwrap();
+    session.eval("fun: inc 1 + ;", &mut out).unwrap();
+    session.eval("fun: twice inc inc ;", &mut out).unwrap();
+    session.eval("fun: inc inc inc ;", &mut out).unwrap();
+    session.eval("1 inc _x_print_int", &mut out).unwrap();
+    // twice still uses the inc that existed when it was defined
+    session.eval("1 twice _x_print_int", &mut out).unwrap();
+    assert_eq!(String::from_utf8(out).unwrap(), "33");
+
+    // the same goes for purity, tidy calls the helper that was pure when tidy was compiled
+    for line in [
+        "fun: helper ;",
+        "fun: tidy helper ;",
+        "act: helper ;",
+        "fun: fine tidy ;",
+    ] {
+        session.eval(line, &mut vec![]).unwrap();
+    }
+}
+
+#[test]
+fn exit_codes() {
+    use crate::{bash, exit_code, interpret};
+
+    let (status, stderr) = bash("echo oops >&2; exit 3").unwrap();
+    assert_eq!(exit_code(status), 3);
+    assert_eq!(stderr, "oops\n");
+    assert_eq!(interpret("exit 3"), 3);
+    // killed by SIGTERM, like a shell reports it
+    #[cfg(unix)]
+    assert_eq!(interpret("kill -TERM $$"), 143);
+    assert_eq!(interpret("true"), 0);
+}
+
+#[test]
+fn build_steps() {
+    use crate::run_step;
+
+    assert_eq!(run_step("true"), Ok(()));
+    // targets leave out the steps they do not need
+    assert_eq!(run_step(""), Ok(()));
+    assert_eq!(
+        run_step("bash -c 'exit 3'"),
+        Err("`bash -c 'exit 3'` exited with code 3".to_string())
+    );
 }
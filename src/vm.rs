@@ -0,0 +1,428 @@
+/*
+* Copyright (C) 2025 Filip Chovanec
+*
+* This program is free software: you can redistribute it and/or modify
+* it under the terms of the GNU General Public License as published by
+* the Free Software Foundation, either version 3 of the License, or
+* (at your option) any later version.
+*
+* This program is distributed in the hope that it will be useful,
+* but WITHOUT ANY WARRANTY; without even the implied warranty of
+* MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+* GNU General Public License for more details.
+*
+* You should have received a copy of the GNU General Public License
+* along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+// runs the bytecode of the vm backend inside dryftc, no external tools needed
+//
+// the text format has one instruction per line, lines starting with ; are comments
+//   fun NAME      start of a method, ends at the last ret before the next fun
+//   call NAME     call a method
+//   native NAME   call a symbol provided by the vm, see Native
+//   push INT      str HEX       push an integer or a string given as hex bytes
+//   load VAR      store VAR     variables are local to the method
+//   gload VAR     gstore VAR    except for the ones of the REPL, which live as long as the machine
+//   label N       jmp N         jz N    pops and jumps if it was zero
+//   ret           and every builtin by its own name, see Prim
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io::Write;
+
+const STACK_SIZE: usize = 1000; // same as the native backends
+const CALL_DEPTH: usize = 1000; // frames live on the heap, this keeps a runaway program from eating it
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Prim {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    Copy,
+    Drop,
+    Swap,
+    Eq,
+    Ne,
+    Not,
+    And,
+    Or,
+    Gt,
+    Ge,
+    Lt,
+    Le,
+    Xor,
+}
+
+impl Prim {
+    fn parse(name: &str) -> Option<Self> {
+        Some(match name {
+            "add" => Prim::Add,
+            "sub" => Prim::Sub,
+            "mul" => Prim::Mul,
+            "div" => Prim::Div,
+            "mod" => Prim::Mod,
+            "copy" => Prim::Copy,
+            "drop" => Prim::Drop,
+            "swap" => Prim::Swap,
+            "eq" => Prim::Eq,
+            "ne" => Prim::Ne,
+            "not" => Prim::Not,
+            "and" => Prim::And,
+            "or" => Prim::Or,
+            "gt" => Prim::Gt,
+            "ge" => Prim::Ge,
+            "lt" => Prim::Lt,
+            "le" => Prim::Le,
+            "xor" => Prim::Xor,
+            _ => return None,
+        })
+    }
+}
+
+// linked in symbols the vm implements itself, named like their native/stdc counterparts
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Native {
+    PrintString,
+    PrintInt,
+}
+
+impl Native {
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "_x_print_string" => Some(Native::PrintString),
+            "_x_print_int" => Some(Native::PrintInt),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Op {
+    Push(i64),
+    Str(usize),  // index into Program::strings
+    Call(usize), // index into Program::methods
+    Native(Native),
+    Prim(Prim),
+    Load(usize), // slot in the current frame
+    Store(usize),
+    GLoad(usize), // slot in Machine::globals
+    GStore(usize),
+    Jmp(usize), // absolute position in Program::code
+    Jz(usize),
+    Ret,
+}
+
+#[derive(Debug)]
+pub struct Method {
+    pub name: String,
+    pub start: usize,
+    pub locals: usize,
+}
+
+#[derive(Debug, Default)]
+pub struct Program {
+    pub code: Vec<Op>,
+    pub methods: Vec<Method>,
+    pub strings: Vec<String>,
+    pub globals: Vec<String>,
+}
+
+impl Program {
+    // the newest definition, which is what running a program or a REPL line starts from
+    pub fn method(&self, name: &str) -> Option<usize> {
+        self.methods.iter().rposition(|m| m.name == name)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct VmError(pub String);
+
+impl fmt::Display for VmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[DRYFT VM] {}", self.0)
+    }
+}
+
+// the bytecode text into ops, every name gets resolved here
+pub fn assemble(text: &str) -> Result<Program, VmError> {
+    let mut program = Program::default();
+
+    // jumps may come before their label, so those get patched once everything is read
+    let mut jumps: Vec<(usize, String, usize)> = vec![];
+    let mut labels: HashMap<String, usize> = HashMap::new();
+    let mut locals: HashMap<String, usize> = HashMap::new();
+
+    for (n, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with(';') {
+            continue;
+        }
+        let (op, arg) = line.split_once(' ').unwrap_or((line, ""));
+        let err = |msg: &str| VmError(format!("line {}: {msg}", n + 1));
+        let here = program.code.len();
+
+        let op = match op {
+            "fun" => {
+                // labels are unique in the whole file, variables only in their method
+                locals.clear();
+                program.methods.push(Method {
+                    name: arg.to_string(),
+                    start: here,
+                    locals: 0,
+                });
+                continue;
+            }
+            "label" => {
+                labels.insert(arg.to_string(), here);
+                continue;
+            }
+            "push" => Op::Push(arg.parse().map_err(|_| err(&format!("Invalid integer {arg}")))?),
+            "str" => {
+                let bytes = (0..arg.len())
+                    .step_by(2)
+                    .map(|i| u8::from_str_radix(arg.get(i..i + 2).unwrap_or("?"), 16))
+                    .collect::<Result<Vec<u8>, _>>()
+                    .map_err(|_| err("Invalid string literal"))?;
+                program.strings.push(String::from_utf8_lossy(&bytes).to_string());
+                Op::Str(program.strings.len() - 1)
+            }
+            "call" => {
+                // the newest definition before the calling method, the one the compiler checked the
+                // call against, so redefining a word in the REPL does not change the old callers
+                let before = program.methods.len().saturating_sub(1);
+                let m = program.methods[..before]
+                    .iter()
+                    .rposition(|m| m.name == arg)
+                    .ok_or_else(|| err(&format!("Unknown method {arg}")))?;
+                Op::Call(m)
+            }
+            "native" => Op::Native(Native::parse(arg).ok_or_else(|| {
+                err(&format!(
+                    "Unknown native symbol {arg}, the vm only provides _x_print_string and _x_print_int"
+                ))
+            })?),
+            "load" | "store" => {
+                let Some(method) = program.methods.last_mut() else {
+                    return Err(err("Variable outside of a method"));
+                };
+                let next = locals.len();
+                let slot = *locals.entry(arg.to_string()).or_insert(next);
+                method.locals = locals.len();
+                if op == "load" {
+                    Op::Load(slot)
+                } else {
+                    Op::Store(slot)
+                }
+            }
+            "gload" | "gstore" => {
+                let slot = match program.globals.iter().position(|g| g == arg) {
+                    Some(slot) => slot,
+                    None => {
+                        program.globals.push(arg.to_string());
+                        program.globals.len() - 1
+                    }
+                };
+                if op == "gload" {
+                    Op::GLoad(slot)
+                } else {
+                    Op::GStore(slot)
+                }
+            }
+            "jmp" | "jz" => {
+                jumps.push((here, arg.to_string(), n + 1));
+                if op == "jmp" {
+                    Op::Jmp(0)
+                } else {
+                    Op::Jz(0)
+                }
+            }
+            "ret" => Op::Ret,
+            prim => Op::Prim(Prim::parse(prim).ok_or_else(|| err(&format!("Unknown instruction {prim}")))?),
+        };
+        program.code.push(op);
+    }
+
+    for (at, label, line) in jumps {
+        let Some(target) = labels.get(&label) else {
+            return Err(VmError(format!("line {line}: Unknown label {label}")));
+        };
+        program.code[at] = match program.code[at] {
+            Op::Jz(_) => Op::Jz(*target),
+            _ => Op::Jmp(*target),
+        };
+    }
+
+    Ok(program)
+}
+
+struct Frame {
+    ret: usize, // where to continue in the caller
+    locals: Vec<i64>,
+}
+
+// the data stack and call frames, kept between runs so a REPL can build on them
+#[derive(Default)]
+pub struct Machine {
+    pub stack: Vec<i64>,
+    pub globals: Vec<i64>,
+}
+
+impl Machine {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn pop(&mut self) -> Result<i64, VmError> {
+        self.stack
+            .pop()
+            .ok_or_else(|| VmError("Stack underflow".to_string()))
+    }
+
+    fn push(&mut self, x: i64) -> Result<(), VmError> {
+        if self.stack.len() >= STACK_SIZE {
+            return Err(VmError("Stack overflow".to_string()));
+        }
+        self.stack.push(x);
+        Ok(())
+    }
+
+    // runs a method until it returns, output of the print natives goes to out
+    pub fn call(
+        &mut self,
+        program: &Program,
+        name: &str,
+        out: &mut dyn Write,
+    ) -> Result<(), VmError> {
+        let Some(entry) = program.method(name) else {
+            return Err(VmError(format!("No method called {name}")));
+        };
+
+        let mut frames = vec![Frame {
+            ret: usize::MAX,
+            locals: vec![0; program.methods[entry].locals],
+        }];
+        let mut pc = program.methods[entry].start;
+        // programs only ever grow between runs, so known globals keep their slots
+        self.globals.resize(program.globals.len(), 0);
+
+        while let Some(frame) = frames.last_mut() {
+            let Some(op) = program.code.get(pc) else {
+                return Err(VmError("Ran past the end of the program".to_string()));
+            };
+            pc += 1;
+
+            match *op {
+                Op::Push(x) => self.push(x)?,
+                Op::Str(i) => self.push(i as i64)?,
+                Op::Call(m) => {
+                    if frames.len() >= CALL_DEPTH {
+                        return Err(VmError("Call stack overflow".to_string()));
+                    }
+                    let callee = &program.methods[m];
+                    frames.push(Frame {
+                        ret: pc,
+                        locals: vec![0; callee.locals],
+                    });
+                    pc = callee.start;
+                }
+                Op::Native(n) => self.native(program, n, out)?,
+                Op::Prim(p) => self.prim(p)?,
+                Op::Load(slot) => {
+                    let x = frame.locals[slot];
+                    self.push(x)?;
+                }
+                Op::Store(slot) => {
+                    let x = self.pop()?;
+                    frames.last_mut().unwrap().locals[slot] = x;
+                }
+                Op::GLoad(slot) => self.push(self.globals[slot])?,
+                Op::GStore(slot) => self.globals[slot] = self.pop()?,
+                Op::Jmp(target) => pc = target,
+                Op::Jz(target) => {
+                    if self.pop()? == 0 {
+                        pc = target;
+                    }
+                }
+                Op::Ret => {
+                    pc = frame.ret;
+                    frames.pop();
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn native(
+        &mut self,
+        program: &Program,
+        native: Native,
+        out: &mut dyn Write,
+    ) -> Result<(), VmError> {
+        let x = self.pop()?;
+        let written = match native {
+            Native::PrintString => {
+                let Some(s) = usize::try_from(x).ok().and_then(|i| program.strings.get(i)) else {
+                    return Err(VmError(format!("{x} is not a string")));
+                };
+                out.write_all(s.as_bytes())
+            }
+            Native::PrintInt => write!(out, "{x}"),
+        };
+        written.map_err(|e| VmError(e.to_string()))
+    }
+
+    fn prim(&mut self, prim: Prim) -> Result<(), VmError> {
+        match prim {
+            Prim::Copy => {
+                let a = self.pop()?;
+                self.push(a)?;
+                self.push(a)
+            }
+            Prim::Drop => self.pop().map(|_| ()),
+            Prim::Swap => {
+                let b = self.pop()?;
+                let a = self.pop()?;
+                self.push(b)?;
+                self.push(a)
+            }
+            Prim::Not => {
+                let a = self.pop()?;
+                self.push((a == 0) as i64)
+            }
+            binary => {
+                let b = self.pop()?;
+                let a = self.pop()?;
+                let r = match binary {
+                    Prim::Add => a.wrapping_add(b),
+                    Prim::Sub => a.wrapping_sub(b),
+                    Prim::Mul => a.wrapping_mul(b),
+                    Prim::Div | Prim::Mod if b == 0 => {
+                        return Err(VmError("Division by zero".to_string()))
+                    }
+                    Prim::Div => a.wrapping_div(b),
+                    Prim::Mod => a.wrapping_rem(b),
+                    Prim::Eq => (a == b) as i64,
+                    Prim::Ne => (a != b) as i64,
+                    Prim::And => (a != 0 && b != 0) as i64,
+                    Prim::Or => (a != 0 || b != 0) as i64,
+                    Prim::Gt => (a > b) as i64,
+                    Prim::Ge => (a >= b) as i64,
+                    Prim::Lt => (a < b) as i64,
+                    Prim::Le => (a <= b) as i64,
+                    Prim::Xor => a ^ b,
+                    Prim::Copy | Prim::Drop | Prim::Swap | Prim::Not => unreachable!(),
+                };
+                self.push(r)
+            }
+        }
+    }
+}
+
+// assemble and run the main act of a bytecode file
+pub fn run(text: &str, out: &mut dyn Write) -> Result<(), VmError> {
+    let program = assemble(text)?;
+    Machine::new().call(&program, "main", out)
+}